@@ -0,0 +1,217 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use serde::{self, Deserialize};
+use std::{collections::HashMap, fs};
+use toml;
+
+use crate::rules::{compile_rules, Rule};
+
+fn default_config() -> String {
+    format!(
+        r#"timeout = 3
+        dnd_override = 2"#,
+    )
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CategoryConfig {
+    pub timeout: Option<u64>,
+    pub urgency: Option<i32>,
+}
+
+/// Limits for the stored notifications and the history, 0 disables a limit.
+/// Only closed notifications are evicted, lower urgencies and older notifications first.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub max_count: usize,
+    /// Maximum age in seconds.
+    pub max_age: u64,
+    /// Maximum amount of history records, records of stored notifications are always kept.
+    pub history_max_count: usize,
+    /// Maximum age of history records in seconds.
+    pub history_max_age: u64,
+    /// Maximum size of the image data held in memory, larger images are dropped first.
+    pub max_image_bytes: usize,
+    pub low: Option<UrgencyRetention>,
+    pub normal: Option<UrgencyRetention>,
+    pub critical: Option<UrgencyRetention>,
+}
+
+/// Overrides the retention for one urgency.
+#[derive(Deserialize, Clone)]
+pub struct UrgencyRetention {
+    pub max_age: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_count: 1000,
+            max_age: 0,
+            history_max_count: 10000,
+            history_max_age: 90 * 24 * 60 * 60,
+            max_image_bytes: 64 * 1024 * 1024,
+            low: None,
+            normal: None,
+            critical: None,
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// Returns the maximum age in seconds for the given urgency, 0 keeps notifications forever.
+    pub fn max_age_for(&self, urgency: i32) -> u64 {
+        let urgency_retention = match urgency {
+            0 => &self.low,
+            1 => &self.normal,
+            _ => &self.critical,
+        };
+        urgency_retention
+            .as_ref()
+            .map_or(self.max_age, |retention| retention.max_age)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    /// The file this config was read from, used when reloading.
+    #[serde(skip)]
+    pub path: String,
+    pub timeout: u64,
+    pub dnd_override: i32,
+    pub category: HashMap<String, CategoryConfig>,
+    pub sound: bool,
+    pub sound_player: String,
+    pub sound_theme: String,
+    pub link_opener: String,
+    /// Schemes of links that may be opened, compared case insensitively.
+    pub link_schemes: Vec<String>,
+    pub retention: RetentionConfig,
+    /// The [[rule]] array, evaluated in order for every notification.
+    #[serde(skip)]
+    pub rules: Vec<Rule>,
+}
+
+impl Config {
+    /// Returns the config for a category like "im.received",
+    /// falling back to the class of the category, "im" in this case.
+    pub fn category(&self, category: &str) -> Option<&CategoryConfig> {
+        self.category.get(category).or_else(|| {
+            category
+                .split_once('.')
+                .and_then(|(class, _)| self.category.get(class))
+        })
+    }
+
+    /// Returns the scheme of a link if it may be opened, links without a scheme are refused.
+    pub fn allowed_link_scheme<'a>(&self, uri: &'a str) -> Result<&'a str, String> {
+        let scheme = uri
+            .split_once(':')
+            .map(|(scheme, _)| scheme)
+            .filter(|scheme| {
+                scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+            })
+            .ok_or_else(|| format!("{} has no scheme", uri))?;
+        if self
+            .link_schemes
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(scheme))
+        {
+            Ok(scheme)
+        } else {
+            Err(format!("{} links are not allowed", scheme))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConfigOptional {
+    timeout: Option<u64>,
+    dnd_override: Option<i32>,
+    category: Option<HashMap<String, CategoryConfig>>,
+    sound: Option<bool>,
+    sound_player: Option<String>,
+    sound_theme: Option<String>,
+    link_opener: Option<String>,
+    link_schemes: Option<Vec<String>>,
+    retention: Option<RetentionConfig>,
+    /// Parsed one by one, so that an invalid rule doesn't discard the rest of the config.
+    rule: Option<Vec<toml::Value>>,
+}
+
+/// Checks that the config file exists and can be parsed.
+pub fn check_config(path: &str) -> Result<(), String> {
+    let contents =
+        fs::read_to_string(path).map_err(|error| format!("Could not read {}: {}", path, error))?;
+    let parsed_conf = toml::from_str::<ConfigOptional>(&contents)
+        .map_err(|error| format!("Invalid config {}: {}", path, error))?;
+    let (_, errors) = compile_rules(parsed_conf.rule.unwrap_or_default());
+    if !errors.is_empty() {
+        return Err(format!("Invalid config {}: {}", path, errors.join(", ")));
+    }
+    Ok(())
+}
+
+pub fn parse_config(path: &str) -> Config {
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(_) => default_config(),
+    };
+    let parsed_conf: ConfigOptional = match toml::from_str(&contents) {
+        Ok(d) => d,
+        Err(error) => {
+            println!(
+                "Could not parse {}, using the default config: {}",
+                path, error
+            );
+            toml::from_str(&default_config()).unwrap()
+        }
+    };
+    let (rules, errors) = compile_rules(parsed_conf.rule.unwrap_or_default());
+    for error in errors {
+        println!("Ignoring an invalid rule of {}: {}", path, error);
+    }
+    Config {
+        path: path.to_string(),
+        timeout: parsed_conf.timeout.unwrap_or_else(|| 3),
+        dnd_override: parsed_conf.dnd_override.unwrap_or_else(|| 2),
+        category: parsed_conf.category.unwrap_or_default(),
+        sound: parsed_conf.sound.unwrap_or(false),
+        sound_player: parsed_conf
+            .sound_player
+            .unwrap_or_else(|| "paplay".to_string()),
+        sound_theme: parsed_conf
+            .sound_theme
+            .unwrap_or_else(|| "freedesktop".to_string()),
+        link_opener: parsed_conf
+            .link_opener
+            .unwrap_or_else(|| "xdg-open".to_string()),
+        link_schemes: parsed_conf.link_schemes.unwrap_or_else(|| {
+            ["http", "https", "mailto"]
+                .into_iter()
+                .map(|scheme| scheme.to_string())
+                .collect()
+        }),
+        retention: parsed_conf.retention.unwrap_or_default(),
+        rules,
+    }
+}
@@ -0,0 +1,128 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+#[derive(Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct DesktopEntry {
+    pub name: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// Caches desktop entry lookups, as the same applications tend to send notifications over and over.
+/// Entries that could not be found are cached as well.
+pub struct DesktopEntryCache {
+    entries: HashMap<String, Option<DesktopEntry>>,
+}
+
+impl DesktopEntryCache {
+    pub fn create() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn lookup(&mut self, desktop_entry: &str) -> Option<DesktopEntry> {
+        if let Some(entry) = self.entries.get(desktop_entry) {
+            return entry.clone();
+        }
+        let entry = find_desktop_entry(desktop_entry);
+        self.insert(desktop_entry, entry.clone());
+        entry
+    }
+
+    /// Returns the cached result without scanning the data directories, None if it is not cached yet.
+    pub fn cached(&self, desktop_entry: &str) -> Option<Option<DesktopEntry>> {
+        self.entries.get(desktop_entry).cloned()
+    }
+
+    pub fn insert(&mut self, desktop_entry: &str, entry: Option<DesktopEntry>) {
+        self.entries.insert(desktop_entry.to_string(), entry);
+    }
+}
+
+/// Returns the XDG data directories in order of preference.
+pub fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    match env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => dirs.push(PathBuf::from(dir)),
+        _ => {
+            if let Ok(home) = env::var("HOME") {
+                dirs.push(PathBuf::from(home).join(".local/share"));
+            }
+        }
+    }
+    let data_dirs = match env::var("XDG_DATA_DIRS") {
+        Ok(data_dirs) if !data_dirs.is_empty() => data_dirs,
+        _ => "/usr/local/share:/usr/share".to_string(),
+    };
+    for dir in data_dirs.split(':').filter(|dir| !dir.is_empty()) {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+pub fn find_desktop_entry(desktop_entry: &str) -> Option<DesktopEntry> {
+    let name = desktop_entry.trim_end_matches(".desktop");
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    let lowercase = name.to_lowercase();
+    for dir in data_dirs() {
+        let applications = dir.join("applications");
+        for candidate in [name, lowercase.as_str()] {
+            let path = applications.join(format!("{}.desktop", candidate));
+            if !path.is_file() {
+                continue;
+            }
+            if let Ok(contents) = fs::read_to_string(path) {
+                return Some(parse_desktop_entry(&contents));
+            }
+        }
+    }
+    None
+}
+
+fn parse_desktop_entry(contents: &str) -> DesktopEntry {
+    let mut entry = DesktopEntry {
+        name: None,
+        icon: None,
+    };
+    let mut in_main_group = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_main_group = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_main_group {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match key.trim() {
+                "Name" => entry.name = Some(value.to_string()),
+                "Icon" => entry.icon = Some(value.to_string()),
+                _ => (),
+            }
+        }
+    }
+    entry
+}
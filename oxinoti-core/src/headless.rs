@@ -0,0 +1,159 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::Config, timeout_millis, CloseReason, Notification, NotificationSink, SinkCloser,
+};
+
+/// Presents notifications without any display by writing them as JSON lines.
+/// Timeouts are simulated by expiring notifications in the daemon, just like the popups do.
+pub struct HeadlessSink {
+    output: Arc<Mutex<Box<dyn Write + Send>>>,
+    config: Mutex<Arc<Config>>,
+    /// Expires notifications in the daemon, set once the server is created.
+    closer: Mutex<Option<SinkCloser>>,
+    /// Counts how often each notification was shown, a replacement restarts the timeout.
+    shown: Arc<Mutex<HashMap<u32, u64>>>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Show {
+        id: u32,
+        app_name: &'a str,
+        summary: &'a str,
+        body: &'a str,
+        urgency: i32,
+        category: Option<&'a str>,
+        actions: Vec<Action<'a>>,
+        expire_timeout: i32,
+        resident: bool,
+        transient: bool,
+    },
+    Close {
+        id: u32,
+        reason: u32,
+    },
+}
+
+#[derive(Serialize)]
+struct Action<'a> {
+    key: &'a str,
+    label: &'a str,
+}
+
+impl HeadlessSink {
+    pub fn create(output: Box<dyn Write + Send>, config: Arc<Config>) -> Self {
+        Self {
+            output: Arc::new(Mutex::new(output)),
+            config: Mutex::new(config),
+            closer: Mutex::new(None),
+            shown: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn write(&self, event: &Event) {
+        let line = serde_json::to_string(event).expect("Failed to serialize event.");
+        let mut output = self.output.lock().unwrap();
+        if writeln!(output, "{}", line)
+            .and_then(|_| output.flush())
+            .is_err()
+        {
+            println!("Could not write notification event");
+        }
+    }
+
+    fn expire_after(&self, id: u32, generation: u64, millis: u64) {
+        let Some(closer) = self.closer.lock().unwrap().clone() else {
+            return;
+        };
+        let shown = self.shown.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(millis));
+            {
+                let mut shown = shown.lock().unwrap();
+                if shown.get(&id) != Some(&generation) {
+                    return;
+                }
+                shown.remove(&id);
+            }
+            closer.close(id, CloseReason::Expired);
+        });
+    }
+}
+
+impl NotificationSink for HeadlessSink {
+    fn show(&self, notification: Notification) {
+        let actions = notification
+            .actions
+            .chunks_exact(2)
+            .map(|pair| Action {
+                key: &pair[0],
+                label: &pair[1],
+            })
+            .collect();
+        self.write(&Event::Show {
+            id: notification.id,
+            app_name: notification.display_name(),
+            summary: &notification.summary,
+            body: &notification.body,
+            urgency: notification.urgency.to_i32(),
+            category: notification.category.as_deref(),
+            actions,
+            expire_timeout: notification.expire_timeout,
+            resident: notification.resident,
+            transient: notification.transient,
+        });
+        let generation = {
+            let mut shown = self.shown.lock().unwrap();
+            let generation = shown.entry(notification.id).or_insert(0);
+            *generation += 1;
+            *generation
+        };
+        let millis = timeout_millis(notification.expire_timeout, &self.config.lock().unwrap());
+        if millis > 0 {
+            self.expire_after(notification.id, generation, millis);
+        }
+    }
+
+    fn close(&self, id: u32, reason: CloseReason) {
+        self.shown.lock().unwrap().remove(&id);
+        self.write(&Event::Close {
+            id,
+            reason: reason.to_u32(),
+        });
+    }
+
+    fn reload(&self, config: Arc<Config>) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    fn connect_closer(&self, closer: SinkCloser) {
+        *self.closer.lock().unwrap() = Some(closer);
+    }
+}
@@ -0,0 +1,319 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Notification, Urgency};
+
+/// A notification as it is kept in the history, the image data is not part of it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryRecord {
+    pub id: u32,
+    /// Seconds since the unix epoch.
+    pub received: u64,
+    pub app_name: String,
+    pub app_icon: String,
+    pub summary: String,
+    pub body: String,
+    pub actions: Vec<String>,
+    pub expire_timeout: i32,
+    pub urgency: i32,
+    pub category: Option<String>,
+    pub desktop_entry: Option<String>,
+    pub image_path: Option<String>,
+    pub progress: Option<i32>,
+    pub resident: bool,
+    /// The reason of the last NotificationClosed, None while the notification is open.
+    pub close_reason: Option<u32>,
+    /// The last action that was invoked on the notification.
+    pub action: Option<String>,
+    /// Set when the user interacted with the notification or a client marked it as read.
+    #[serde(default)]
+    pub read: bool,
+    /// Whether the daemon still stores the notification, e.g. for GetAllNotifications.
+    pub stored: bool,
+}
+
+impl HistoryRecord {
+    pub fn from_notification(notification: &Notification) -> Self {
+        Self {
+            id: notification.id,
+            received: notification.received,
+            app_name: notification.app_name.clone(),
+            app_icon: notification.app_icon.clone(),
+            summary: notification.summary.clone(),
+            body: notification.body.clone(),
+            actions: notification.actions.clone(),
+            expire_timeout: notification.expire_timeout,
+            urgency: notification.urgency.to_i32(),
+            category: notification.category.clone(),
+            desktop_entry: notification.desktop_entry.clone(),
+            image_path: notification.image_path.clone(),
+            progress: notification.progress,
+            resident: notification.resident,
+            close_reason: None,
+            action: None,
+            read: false,
+            stored: true,
+        }
+    }
+
+    /// Recreates the stored notification, e.g. after a restart.
+    pub fn to_notification(&self) -> Notification {
+        Notification {
+            id: self.id,
+            received: self.received,
+            app_name: self.app_name.clone(),
+            replaces_id: 0,
+            app_icon: self.app_icon.clone(),
+            summary: self.summary.clone(),
+            body: self.body.clone(),
+            actions: self.actions.clone(),
+            action_icons: false,
+            expire_timeout: self.expire_timeout,
+            urgency: Urgency::from_i32(self.urgency).unwrap_or(Urgency::Low),
+            image_path: self.image_path.clone(),
+            progress: self.progress,
+            image_data: None,
+            resident: self.resident,
+            transient: false,
+            category: self.category.clone(),
+            desktop_entry: self.desktop_entry.clone(),
+            desktop_app: None,
+            sound_file: None,
+            sound_name: None,
+            suppress_sound: false,
+            css_classes: Vec::new(),
+        }
+    }
+}
+
+/// Filters of a history query, filters that are not set match every record.
+#[derive(Default)]
+pub struct HistoryFilter {
+    pub app_name: Option<String>,
+    pub urgency: Option<i32>,
+    pub category: Option<String>,
+    /// Only records received at or after this time, in seconds since the unix epoch.
+    pub since: Option<u64>,
+    /// Only records received before this time, in seconds since the unix epoch.
+    pub until: Option<u64>,
+    /// Case insensitive text that has to be part of the summary or body.
+    pub text: Option<String>,
+    pub read: Option<bool>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, record: &HistoryRecord) -> bool {
+        let text_matches = |text: &str| {
+            let text = text.to_lowercase();
+            record.summary.to_lowercase().contains(&text)
+                || record.body.to_lowercase().contains(&text)
+        };
+        self.app_name
+            .as_ref()
+            .map_or(true, |app_name| &record.app_name == app_name)
+            && self
+                .urgency
+                .map_or(true, |urgency| record.urgency == urgency)
+            && self
+                .category
+                .as_ref()
+                .map_or(true, |category| record.category.as_ref() == Some(category))
+            && self.since.map_or(true, |since| record.received >= since)
+            && self.until.map_or(true, |until| record.received < until)
+            && self.text.as_deref().map_or(true, text_matches)
+            && self.read.map_or(true, |read| record.read == read)
+    }
+}
+
+/// Keeps every notification, in a JSON lines file if one is given.
+/// Each change appends the whole record, the last line of an id wins.
+/// Removed records stay in the file until it is compacted to one line per record.
+pub struct History {
+    file: Option<(PathBuf, File)>,
+    records: BTreeMap<u32, HistoryRecord>,
+    /// Set when records were removed since the file was last compacted.
+    removed: bool,
+}
+
+impl History {
+    /// A history that is not written to disk.
+    pub fn in_memory() -> Self {
+        Self {
+            file: None,
+            records: BTreeMap::new(),
+            removed: false,
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut records = BTreeMap::new();
+        if let Ok(file) = File::open(path) {
+            for (number, line) in BufReader::new(file).lines().enumerate() {
+                let line =
+                    line.map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+                match serde_json::from_str::<HistoryRecord>(&line) {
+                    Ok(record) => {
+                        records.insert(record.id, record);
+                    }
+                    Err(error) => println!(
+                        "Ignoring line {} of {}: {}",
+                        number + 1,
+                        path.display(),
+                        error
+                    ),
+                }
+            }
+        }
+        let file = write_records(path, &records)?;
+        Ok(Self {
+            file: Some((path.to_path_buf(), file)),
+            records,
+            removed: false,
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    /// All records, ordered by id.
+    pub fn records(&self) -> impl Iterator<Item = &HistoryRecord> {
+        self.records.values()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&HistoryRecord> {
+        self.records.get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Removes a record, the file keeps it until the next compaction.
+    pub fn remove(&mut self, id: u32) {
+        self.removed |= self.records.remove(&id).is_some();
+    }
+
+    /// Rewrites the file without the removed records.
+    pub fn compact(&mut self) {
+        if !self.removed {
+            return;
+        }
+        if let Some((path, file)) = &mut self.file {
+            match write_records(path, &self.records) {
+                Ok(compacted) => *file = compacted,
+                Err(error) => println!("{}", error),
+            }
+        }
+        self.removed = false;
+    }
+
+    /// Records a new notification, a replacement overwrites the record of the old one.
+    pub fn record(&mut self, notification: &Notification) {
+        let record = HistoryRecord::from_notification(notification);
+        self.records.insert(record.id, record);
+        self.append(notification.id);
+    }
+
+    /// Changes the record with the given id, if there is one.
+    pub fn update(&mut self, id: u32, change: impl FnOnce(&mut HistoryRecord)) {
+        if let Some(record) = self.records.get_mut(&id) {
+            change(record);
+            self.append(id);
+        }
+    }
+
+    /// Returns the matching records starting at offset, newest first, and the number of all matches.
+    /// A limit of 0 returns all records after the offset.
+    pub fn query(
+        &self,
+        filter: &HistoryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> (Vec<&HistoryRecord>, usize) {
+        let mut matches: Vec<&HistoryRecord> = self
+            .records
+            .values()
+            .filter(|record| filter.matches(record))
+            .collect();
+        matches.sort_by_key(|record| Reverse((record.received, record.id)));
+        let total = matches.len();
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let page = matches.into_iter().skip(offset).take(limit).collect();
+        (page, total)
+    }
+
+    fn append(&mut self, id: u32) {
+        if let Some((path, file)) = &mut self.file {
+            let line =
+                serde_json::to_string(&self.records[&id]).expect("Failed to serialize record.");
+            if file.write_all(format!("{}\n", line).as_bytes()).is_err() {
+                println!("Could not write to {}", path.display());
+            }
+        }
+    }
+}
+
+/// Replaces the file with the given records and opens it for appending.
+/// The bodies of notifications are private, so only the user can read the file.
+fn write_records(path: &Path, records: &BTreeMap<u32, HistoryRecord>) -> Result<File, String> {
+    let error = |error: std::io::Error| format!("Could not write {}: {}", path.display(), error);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(error)?;
+    }
+    let mut contents = String::new();
+    for record in records.values() {
+        contents += &serde_json::to_string(record).expect("Failed to serialize record.");
+        contents.push('\n');
+    }
+    let temporary = path.with_extension("tmp");
+    // the mode only applies to new files, a leftover from an earlier run may have other permissions
+    let _ = fs::remove_file(&temporary);
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temporary)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(error)?;
+    fs::rename(&temporary, path).map_err(error)?;
+    OpenOptions::new().append(true).open(path).map_err(error)
+}
+
+/// Seconds since the unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
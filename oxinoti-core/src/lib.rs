@@ -0,0 +1,1890 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+pub mod config;
+pub mod desktop;
+pub mod headless;
+pub mod history;
+pub mod markup;
+pub mod rules;
+pub mod sound;
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
+    fmt::Display,
+    hash::Hash,
+    io, mem,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use dbus::{
+    arg::{self, cast, prop_cast, RefArg, Variant},
+    blocking::{
+        stdintf::org_freedesktop_dbus::{PropertiesPropertiesChanged, RequestNameReply},
+        Connection,
+    },
+    channel::{MatchingReceiver, Sender},
+    message::{MatchRule, SignalArgs},
+    Message,
+};
+use dbus_crossroads::{Context, MethodErr};
+
+use crate::{
+    config::{parse_config, Config, RetentionConfig},
+    desktop::{find_desktop_entry, DesktopEntry, DesktopEntryCache},
+    history::{History, HistoryFilter, HistoryRecord},
+    sound::SoundRequest,
+};
+
+#[derive(Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub struct ImageData {
+    pub width: i32,
+    pub height: i32,
+    pub rowstride: i32,
+    pub has_alpha: bool,
+    pub bits_per_sample: i32,
+    pub channels: i32,
+    pub data: Vec<u8>,
+}
+
+impl ImageData {
+    /// Parses the (iiibiiay) structure of the image-data hint.
+    pub fn from_hint(raw: &VecDeque<Box<dyn RefArg>>) -> Result<ImageData, &'static str> {
+        if raw.len() != 7 {
+            return Err("image data needs exactly 7 fields");
+        }
+        let int_at = |index: usize| -> Result<i32, &'static str> {
+            cast::<i32>(&raw[index])
+                .copied()
+                .ok_or("expected an integer in image data")
+        };
+        let image_data = ImageData {
+            width: int_at(0)?,
+            height: int_at(1)?,
+            rowstride: int_at(2)?,
+            has_alpha: *cast::<bool>(&raw[3]).ok_or("expected a boolean for has_alpha")?,
+            bits_per_sample: int_at(4)?,
+            channels: int_at(5)?,
+            data: cast::<Vec<u8>>(&raw[6])
+                .ok_or("expected a byte array for the image data")?
+                .clone(),
+        };
+        image_data.validate()?;
+        Ok(image_data)
+    }
+
+    /// Checks that dimensions, format and the length of the data are consistent.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.width <= 0 || self.height <= 0 {
+            return Err("width and height need to be positive");
+        }
+        if self.bits_per_sample != 8 && self.bits_per_sample != 16 {
+            return Err("only 8 or 16 bits per sample are supported");
+        }
+        let expected_channels = if self.has_alpha { [2, 4] } else { [1, 3] };
+        if !expected_channels.contains(&self.channels) {
+            return Err("number of channels does not match has_alpha");
+        }
+        let row_length = self.width as usize * self.pixel_size();
+        if self.rowstride <= 0 || (self.rowstride as usize) < row_length {
+            return Err("rowstride is smaller than a row of pixels");
+        }
+        let needed = (self.height as usize - 1) * self.rowstride as usize + row_length;
+        if self.data.len() < needed {
+            return Err("image data is shorter than width, height and rowstride require");
+        }
+        Ok(())
+    }
+
+    /// Size of a pixel in bytes.
+    pub fn pixel_size(&self) -> usize {
+        (self.channels as usize * self.bits_per_sample as usize).div_ceil(8)
+    }
+
+    /// Converts the image to 8 bit RGB or RGBA, the only formats gdk-pixbuf can handle.
+    /// Returns None for invalid image data.
+    pub fn to_rgb8(&self) -> Option<ImageData> {
+        if self.validate().is_err() {
+            return None;
+        }
+        if self.bits_per_sample == 8 && self.channels >= 3 {
+            return Some(self.clone());
+        }
+        let channels = self.channels as usize;
+        let sample_size = self.bits_per_sample as usize / 8;
+        let out_channels = if self.has_alpha { 4 } else { 3 };
+        let mut data =
+            Vec::with_capacity(self.width as usize * self.height as usize * out_channels);
+        for y in 0..self.height as usize {
+            let row = &self.data[y * self.rowstride as usize..];
+            for x in 0..self.width as usize {
+                let sample = |channel: usize| -> u8 {
+                    let offset = (x * channels + channel) * sample_size;
+                    if sample_size == 2 {
+                        (u16::from_ne_bytes([row[offset], row[offset + 1]]) >> 8) as u8
+                    } else {
+                        row[offset]
+                    }
+                };
+                if channels <= 2 {
+                    let gray = sample(0);
+                    data.extend_from_slice(&[gray, gray, gray]);
+                } else {
+                    data.extend_from_slice(&[sample(0), sample(1), sample(2)]);
+                }
+                if self.has_alpha {
+                    data.push(sample(channels - 1));
+                }
+            }
+        }
+        Some(ImageData {
+            width: self.width,
+            height: self.height,
+            rowstride: self.width * out_channels as i32,
+            has_alpha: self.has_alpha,
+            bits_per_sample: 8,
+            channels: out_channels as i32,
+            data,
+        })
+    }
+
+    /// The (iiibiiay) structure used on D-Bus.
+    pub fn into_tuple(self) -> (i32, i32, i32, bool, i32, i32, Vec<u8>) {
+        (
+            self.width,
+            self.height,
+            self.rowstride,
+            self.has_alpha,
+            self.bits_per_sample,
+            self.channels,
+            self.data,
+        )
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            width: -1,
+            height: -1,
+            rowstride: -1,
+            has_alpha: false,
+            bits_per_sample: -1,
+            channels: -1,
+            data: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Urgency {
+    Low,
+    Normal,
+    Urgent,
+}
+
+impl Urgency {
+    pub fn from_i32(value: i32) -> Result<Urgency, &'static str> {
+        match value {
+            0 => Ok(Urgency::Low),
+            1 => Ok(Urgency::Normal),
+            2 => Ok(Urgency::Urgent),
+            _ => Err("invalid number, only 1,2 or 3 allowed"),
+        }
+    }
+    pub fn to_i32(&self) -> i32 {
+        match self {
+            Urgency::Low => 0,
+            Urgency::Normal => 1,
+            Urgency::Urgent => 2,
+        }
+    }
+    pub fn to_str(&self) -> &str {
+        match self {
+            Urgency::Low => "NotificationLow",
+            Urgency::Normal => "NotificationNormal",
+            Urgency::Urgent => "NotificationUrgent",
+        }
+    }
+}
+
+impl Display for Urgency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_i32())
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum CloseReason {
+    Expired,
+    Dismissed,
+    Closed,
+    Undefined,
+}
+
+impl CloseReason {
+    pub fn from_u32(value: u32) -> CloseReason {
+        match value {
+            1 => CloseReason::Expired,
+            2 => CloseReason::Dismissed,
+            3 => CloseReason::Closed,
+            _ => CloseReason::Undefined,
+        }
+    }
+    pub fn to_u32(self) -> u32 {
+        match self {
+            CloseReason::Expired => 1,
+            CloseReason::Dismissed => 2,
+            CloseReason::Closed => 3,
+            CloseReason::Undefined => 4,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, PartialOrd, Ord)]
+pub struct Notification {
+    pub id: u32,
+    /// Seconds since the unix epoch.
+    pub received: u64,
+    pub app_name: String,
+    pub replaces_id: u32,
+    pub app_icon: String,
+    pub summary: String,
+    pub body: String,
+    pub actions: Vec<String>,
+    pub action_icons: bool,
+    pub expire_timeout: i32,
+    pub urgency: Urgency,
+    pub image_path: Option<String>,
+    pub progress: Option<i32>,
+    pub image_data: Option<ImageData>,
+    pub resident: bool,
+    pub transient: bool,
+    pub category: Option<String>,
+    pub desktop_entry: Option<String>,
+    pub desktop_app: Option<DesktopEntry>,
+    pub sound_file: Option<String>,
+    pub sound_name: Option<String>,
+    pub suppress_sound: bool,
+    /// Extra css classes of the popup, added by rules.
+    pub css_classes: Vec<String>,
+}
+
+impl Clone for Notification {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            received: self.received,
+            app_name: self.app_name.clone(),
+            replaces_id: self.replaces_id.clone(),
+            app_icon: self.app_icon.clone(),
+            summary: self.summary.clone(),
+            body: self.body.clone(),
+            actions: self.actions.clone(),
+            action_icons: self.action_icons,
+            expire_timeout: self.expire_timeout.clone(),
+            urgency: self.urgency.clone(),
+            image_path: self.image_path.clone(),
+            progress: self.progress.clone(),
+            image_data: self.image_data.clone(),
+            resident: self.resident,
+            transient: self.transient,
+            category: self.category.clone(),
+            desktop_entry: self.desktop_entry.clone(),
+            desktop_app: self.desktop_app.clone(),
+            sound_file: self.sound_file.clone(),
+            sound_name: self.sound_name.clone(),
+            suppress_sound: self.suppress_sound,
+            css_classes: self.css_classes.clone(),
+        }
+    }
+}
+
+impl Hash for Notification {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.received.hash(state);
+        self.app_name.hash(state);
+        self.replaces_id.hash(state);
+        self.app_icon.hash(state);
+        self.summary.hash(state);
+        self.body.hash(state);
+        self.actions.hash(state);
+        self.action_icons.hash(state);
+        self.expire_timeout.hash(state);
+        self.urgency.to_i32().hash(state);
+        self.image_path.hash(state);
+        self.progress.hash(state);
+        self.resident.hash(state);
+        self.transient.hash(state);
+        self.category.hash(state);
+        self.desktop_entry.hash(state);
+        self.sound_file.hash(state);
+        self.sound_name.hash(state);
+        self.suppress_sound.hash(state);
+        self.css_classes.hash(state);
+    }
+}
+
+impl Notification {
+    /// Creates a notification from the arguments of Notify, invalid hints are reported when verbose.
+    pub fn create(
+        app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        hints: arg::PropMap,
+        expire_timeout: i32,
+        verbose: bool,
+    ) -> Self {
+        let mut urgency = Urgency::Low;
+        let urgency_opt = hints.get("urgency");
+        if urgency_opt.is_some() {
+            let urg = Urgency::from_i32(urgency_opt.unwrap().as_i64().unwrap_or_else(|| 1) as i32);
+            urgency = urg.unwrap_or_else(|_| -> Urgency { Urgency::Low });
+        }
+        // image_path is the deprecated name of image-path from spec 1.1
+        let image_path =
+            string_hint(&hints, "image-path").or_else(|| string_hint(&hints, "image_path"));
+        // image_data and icon_data are the deprecated names of image-data from spec 1.1 and 1.0
+        let mut image_data = None;
+        for name in ["image-data", "image_data", "icon_data"] {
+            let image_data_opt: Option<&VecDeque<Box<dyn RefArg>>> = prop_cast(&hints, name);
+            if image_data_opt.is_none() {
+                continue;
+            }
+            match ImageData::from_hint(image_data_opt.unwrap()) {
+                Ok(data) => {
+                    image_data = Some(data);
+                    break;
+                }
+                Err(error) => {
+                    if verbose {
+                        println!("Ignoring {} hint of {}: {}", name, app_name, error);
+                    }
+                }
+            }
+        }
+        let action_icons = bool_hint(&hints, "action-icons");
+        let resident = bool_hint(&hints, "resident");
+        let transient = bool_hint(&hints, "transient");
+        let category = string_hint(&hints, "category");
+        let desktop_entry = string_hint(&hints, "desktop-entry");
+        let sound_file = string_hint(&hints, "sound-file");
+        let sound_name = string_hint(&hints, "sound-name");
+        let suppress_sound = bool_hint(&hints, "suppress-sound");
+        let mut progress = None;
+        let progress_opt = hints.get("progress");
+        if progress_opt.is_some() {
+            progress = Some(
+                progress_opt
+                    .unwrap()
+                    .as_i64()
+                    .unwrap_or_else(|| -1)
+                    .clamp(-1, 100) as i32,
+            );
+        }
+        Self {
+            id: 0,
+            received: history::now(),
+            app_name,
+            replaces_id,
+            app_icon,
+            summary,
+            body,
+            actions,
+            action_icons,
+            expire_timeout,
+            urgency,
+            image_path,
+            progress,
+            image_data,
+            resident,
+            transient,
+            category,
+            desktop_entry,
+            desktop_app: None,
+            sound_file,
+            sound_name,
+            suppress_sound,
+            css_classes: Vec::new(),
+        }
+    }
+
+    /// Returns the name of the desktop entry if available, otherwise the app_name.
+    pub fn display_name(&self) -> &str {
+        self.desktop_app
+            .as_ref()
+            .and_then(|app| app.name.as_deref())
+            .unwrap_or(&self.app_name)
+    }
+
+    /// Returns the icon of the desktop entry, used when nothing else is available.
+    pub fn fallback_icon(&self) -> Option<String> {
+        self.desktop_app.as_ref().and_then(|app| app.icon.clone())
+    }
+
+    /// Applies the defaults configured for the category of this notification.
+    /// Values sent by the application itself take precedence.
+    pub fn apply_category_config(&mut self, config: &Config, has_urgency: bool) {
+        let category_config = self
+            .category
+            .as_ref()
+            .and_then(|category| config.category(category));
+        if category_config.is_none() {
+            return;
+        }
+        let category_config = category_config.unwrap();
+        if let Some(timeout) = category_config.timeout {
+            if self.expire_timeout < 0 {
+                self.expire_timeout = timeout.saturating_mul(1000).min(i32::MAX as u64) as i32;
+            }
+        }
+        if let Some(urgency) = category_config.urgency {
+            if !has_urgency {
+                self.urgency = Urgency::from_i32(urgency).unwrap_or(Urgency::Low);
+            }
+        }
+    }
+
+    /// Returns the actions as (key, label) pairs.
+    /// The default action and inline-reply are not included, as they are not shown as buttons.
+    pub fn action_pairs(&self) -> Vec<(String, String)> {
+        self.actions
+            .chunks_exact(2)
+            .filter(|pair| pair[0] != "default" && pair[0] != "inline-reply")
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect()
+    }
+
+    pub fn has_action(&self, key: &str) -> bool {
+        self.actions.chunks_exact(2).any(|pair| pair[0] == key)
+    }
+
+    #[allow(dead_code)]
+    pub fn print(&self) {
+        print!(
+            "Notification {} with summary {} from app {}\n
+    Body: {}\n
+    timestamp: {}\n,
+    image-path: {}\n,
+    icon: {}\n",
+            self.id,
+            self.summary,
+            self.app_name,
+            self.body,
+            self.expire_timeout,
+            self.app_icon,
+            self.image_path
+                .clone()
+                .unwrap_or_else(|| "nopic".to_string())
+        );
+    }
+}
+
+pub struct NotificationWrapper {
+    pub notifications: HashMap<u32, Notification>,
+    pub last_notification_id: u32,
+    pub expired: HashSet<u32>,
+    pub transient: HashSet<u32>,
+    pub do_not_disturb: bool,
+    pub notification_center: bool,
+    pub paused: bool,
+    pub queued: Vec<Notification>,
+    pub desktop_entries: DesktopEntryCache,
+    pub config: Arc<Config>,
+    pub verbose: bool,
+    pub sink: Box<dyn NotificationSink>,
+    pub history: History,
+}
+
+/// Receives the notifications that should be presented, e.g. as popups.
+/// The daemon itself only stores notifications, everything visible happens in the sink.
+pub trait NotificationSink: Send {
+    /// Called for new notifications and for replacements of notifications that are shown already.
+    fn show(&self, notification: Notification);
+    /// Called whenever a notification is closed, the sink should remove it if it still shows it.
+    fn close(&self, id: u32, reason: CloseReason);
+    /// Called after the config file was reloaded.
+    fn reload(&self, _config: Arc<Config>) {}
+    /// Returns the activation token of the input that invoked an action, e.g. the click on a popup.
+    /// Actions that are invoked by other clients have none.
+    fn activation_token(&self, _id: u32) -> Option<String> {
+        None
+    }
+    /// Called once when the server is created, sinks that close notifications on their own keep the closer.
+    fn connect_closer(&self, _closer: SinkCloser) {}
+}
+
+/// Lets a sink close notifications without going through the bus, e.g. once their timeout passed.
+#[derive(Clone)]
+pub struct SinkCloser {
+    closed: mpsc::Sender<(u32, CloseReason)>,
+}
+
+impl SinkCloser {
+    /// The daemon closes the notification with its next iteration and emits NotificationClosed.
+    pub fn close(&self, id: u32, reason: CloseReason) {
+        let _ = self.closed.send((id, reason));
+    }
+}
+
+/// The state of the daemon that is exposed as D-Bus properties.
+#[derive(Clone, Copy, PartialEq)]
+pub struct WrapperState {
+    pub do_not_disturb: bool,
+    pub notification_center: bool,
+    pub count: u32,
+    pub paused: bool,
+}
+
+impl NotificationWrapper {
+    pub fn create(sink: impl NotificationSink + 'static, config: Arc<Config>) -> Self {
+        Self {
+            notifications: HashMap::new(),
+            last_notification_id: 0,
+            expired: HashSet::new(),
+            transient: HashSet::new(),
+            do_not_disturb: false,
+            notification_center: false,
+            paused: false,
+            queued: Vec::new(),
+            desktop_entries: DesktopEntryCache::create(),
+            config,
+            verbose: false,
+            sink: Box::new(sink),
+            history: History::in_memory(),
+        }
+    }
+    /// Restores the notifications that were still stored when the daemon stopped.
+    /// Their popups are gone, so they are treated like expired ones.
+    pub fn load_history(&mut self, history: History) {
+        for record in history.records().filter(|record| record.stored) {
+            let mut notification = record.to_notification();
+            if let Some(desktop_entry) = &notification.desktop_entry {
+                notification.desktop_app = self.desktop_entries.lookup(desktop_entry);
+            }
+            self.notifications.insert(notification.id, notification);
+            self.expired.insert(record.id);
+        }
+        if let Some(record) = history.records().last() {
+            self.last_notification_id = self.last_notification_id.max(record.id);
+        }
+        self.history = history;
+    }
+    fn contains(&self, id: u32) -> bool {
+        self.notifications.contains_key(&id) || self.transient.contains(&id)
+    }
+    /// Returns the id for an incoming notification.
+    /// The requested id is only reused if it replaces a notification that still exists,
+    /// otherwise a new id is allocated, 0 is never handed out as the spec reserves it.
+    pub fn next_id(&mut self, replaces_id: u32) -> u32 {
+        if replaces_id != 0 && self.contains(replaces_id) {
+            return replaces_id;
+        }
+        loop {
+            self.last_notification_id = self.last_notification_id.wrapping_add(1);
+            if self.last_notification_id != 0 && !self.contains(self.last_notification_id) {
+                return self.last_notification_id;
+            }
+        }
+    }
+    /// Stores the notification, transient notifications are only tracked by id and never persisted.
+    pub fn add_notification(&mut self, notification: &mut Notification) {
+        if self.verbose {
+            notification.print();
+        }
+        self.expired.remove(&notification.id);
+        if notification.transient {
+            self.notifications.remove(&notification.id);
+            self.transient.insert(notification.id);
+        } else {
+            self.transient.remove(&notification.id);
+            self.notifications
+                .insert(notification.id, notification.clone());
+            self.history.record(notification);
+            self.apply_retention(Some(notification.id));
+        }
+    }
+    fn is_open(&self, id: u32) -> bool {
+        self.notifications.contains_key(&id) && !self.expired.contains(&id)
+    }
+    /// Evicts stored notifications, history records and images that exceed the retention of the config.
+    /// Only closed notifications are evicted, open ones and keep stay until they are closed.
+    pub fn apply_retention(&mut self, keep: Option<u32>) {
+        let retention = self.config.retention.clone();
+        let now = history::now();
+        let (mut evicted, mut remaining): (Vec<&Notification>, Vec<&Notification>) = self
+            .notifications
+            .values()
+            .filter(|notification| {
+                Some(notification.id) != keep && self.expired.contains(&notification.id)
+            })
+            .partition(|notification| {
+                let max_age = retention.max_age_for(notification.urgency.to_i32());
+                max_age > 0 && now.saturating_sub(notification.received) > max_age
+            });
+        let count = self.notifications.len() - evicted.len();
+        if retention.max_count > 0 && count > retention.max_count {
+            remaining.sort_by_key(|notification| {
+                (
+                    notification.urgency.clone(),
+                    notification.received,
+                    notification.id,
+                )
+            });
+            evicted.extend(remaining.into_iter().take(count - retention.max_count));
+        }
+        let evicted: Vec<u32> = evicted
+            .into_iter()
+            .map(|notification| notification.id)
+            .collect();
+        for id in evicted {
+            self.notifications.remove(&id);
+            self.expired.remove(&id);
+            self.history.update(id, |record| record.stored = false);
+        }
+        self.apply_history_retention(&retention, now);
+        if retention.max_image_bytes > 0 {
+            self.evict_images(retention.max_image_bytes, keep);
+        }
+    }
+    /// Removes history records of notifications that are no longer stored,
+    /// the oldest first once there are more than history_max_count.
+    /// Without a history file only the records of stored notifications are kept.
+    fn apply_history_retention(&mut self, retention: &RetentionConfig, now: u64) {
+        let in_memory = self.history.path().is_none();
+        let (mut removed, mut remaining): (Vec<&HistoryRecord>, Vec<&HistoryRecord>) = self
+            .history
+            .records()
+            .filter(|record| !self.notifications.contains_key(&record.id))
+            .partition(|record| {
+                in_memory
+                    || retention.history_max_age > 0
+                        && now.saturating_sub(record.received) > retention.history_max_age
+            });
+        let count = self.history.len() - removed.len();
+        if retention.history_max_count > 0 && count > retention.history_max_count {
+            remaining.sort_by_key(|record| (record.received, record.id));
+            let excess = count - retention.history_max_count;
+            removed.extend(remaining.into_iter().take(excess));
+        }
+        let removed: Vec<u32> = removed.into_iter().map(|record| record.id).collect();
+        for id in removed {
+            self.history.remove(id);
+        }
+    }
+    /// Drops the image data of the least important notifications until the images fit into max_bytes.
+    fn evict_images(&mut self, max_bytes: usize, keep: Option<u32>) {
+        let image_size = |notification: &Notification| {
+            notification
+                .image_data
+                .as_ref()
+                .map_or(0, |image_data| image_data.data.len())
+        };
+        let mut total: usize = self.notifications.values().map(image_size).sum();
+        if total <= max_bytes {
+            return;
+        }
+        let mut images: Vec<&Notification> = self
+            .notifications
+            .values()
+            .filter(|notification| {
+                notification.image_data.is_some() && Some(notification.id) != keep
+            })
+            .collect();
+        images.sort_by_key(|notification| {
+            (
+                self.is_open(notification.id),
+                notification.urgency.clone(),
+                notification.received,
+                notification.id,
+            )
+        });
+        let mut dropped = Vec::new();
+        for notification in images {
+            if total <= max_bytes {
+                break;
+            }
+            total -= image_size(notification);
+            dropped.push(notification.id);
+        }
+        for id in dropped {
+            if let Some(notification) = self.notifications.get_mut(&id) {
+                notification.image_data = None;
+            }
+        }
+    }
+    /// Applies the retention and compacts the history file, done periodically by the server.
+    pub fn sweep(&mut self) {
+        self.apply_retention(None);
+        self.history.compact();
+    }
+    /// Closes a notification with the given reason.
+    /// Expired notifications are kept for the notification center, everything else is removed.
+    /// Returns true if NotificationClosed still has to be emitted for this id.
+    pub fn close_notification(&mut self, id: u32, reason: CloseReason) -> bool {
+        if self.verbose {
+            println!("Closing notification {} with reason {:?}", id, reason);
+        }
+        if self.transient.remove(&id) {
+            self.sink.close(id, reason);
+            return true;
+        }
+        if !self.notifications.contains_key(&id) {
+            return false;
+        }
+        let already_closed = self.expired.contains(&id);
+        if reason == CloseReason::Expired {
+            self.expired.insert(id);
+        } else {
+            self.notifications.remove(&id);
+            self.expired.remove(&id);
+        }
+        if !already_closed || reason != CloseReason::Expired {
+            self.history.update(id, |record| {
+                record.close_reason = Some(reason.to_u32());
+                record.read |= reason == CloseReason::Dismissed;
+                record.stored = reason == CloseReason::Expired;
+            });
+        }
+        if !already_closed {
+            self.sink.close(id, reason);
+        }
+        !already_closed
+    }
+    /// Removes all notifications and returns the ids that still need a NotificationClosed signal.
+    pub fn clear_all_notifications(&mut self) -> Vec<u32> {
+        let ids: Vec<u32> = self
+            .notifications
+            .keys()
+            .filter(|id| !self.expired.contains(id))
+            .copied()
+            .collect();
+        let removed: Vec<u32> = self.notifications.keys().copied().collect();
+        self.notifications.clear();
+        self.expired.clear();
+        for id in removed {
+            self.history.update(id, |record| {
+                record
+                    .close_reason
+                    .get_or_insert(CloseReason::Undefined.to_u32());
+                record.stored = false;
+            });
+        }
+        for id in ids.iter() {
+            self.sink.close(*id, CloseReason::Undefined);
+        }
+        ids
+    }
+    /// Remembers the action that was invoked on a notification, which also marks it as read.
+    pub fn record_action(&mut self, id: u32, action: &str) {
+        self.history.update(id, |record| {
+            record.action = Some(action.to_string());
+            record.read = true;
+        });
+    }
+    pub fn mark_read(&mut self, ids: &[u32]) {
+        for id in ids {
+            self.history.update(*id, |record| record.read = true);
+        }
+    }
+    /// Notifications persist if they outlive their popup, in the history file or in an open notification center.
+    pub fn persists_notifications(&self) -> bool {
+        self.history.path().is_some() || self.notification_center
+    }
+    pub fn get_all_notifications(&self) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        for notification in self.notifications.values().cloned() {
+            notifications.push(notification);
+        }
+        notifications
+    }
+    pub fn toggle_do_not_disturb(&mut self) -> bool {
+        self.do_not_disturb = !self.do_not_disturb;
+        self.do_not_disturb
+    }
+    pub fn set_do_not_disturb(&mut self, do_not_disturb: bool) {
+        self.do_not_disturb = do_not_disturb;
+    }
+    pub fn toggle_notification_center(&mut self) -> bool {
+        self.notification_center = !self.notification_center;
+        self.notification_center
+    }
+    pub fn set_notification_center(&mut self, open: bool) {
+        self.notification_center = open;
+    }
+    /// Pausing holds back all popups, they are shown once the daemon is resumed.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        if paused {
+            return;
+        }
+        for notification in mem::take(&mut self.queued) {
+            if self.contains(notification.id) {
+                self.sink.show(notification);
+            }
+        }
+    }
+    /// Reads the config file again and hands the new config to the sink.
+    pub fn reload_config(&mut self) {
+        self.config = Arc::new(parse_config(&self.config.path));
+        self.sink.reload(self.config.clone());
+    }
+    pub fn state(&self) -> WrapperState {
+        WrapperState {
+            do_not_disturb: self.do_not_disturb,
+            notification_center: self.notification_center,
+            count: self.notifications.len() as u32,
+            paused: self.paused,
+        }
+    }
+}
+
+/// Creates a PropertiesChanged signal for all properties that differ between both states.
+fn properties_changed(
+    path: &dbus::Path,
+    before: WrapperState,
+    after: WrapperState,
+) -> Option<Message> {
+    let mut changed = arg::PropMap::new();
+    let mut add = |name: &str, value: Box<dyn RefArg>| {
+        changed.insert(name.to_string(), Variant(value));
+    };
+    if before.do_not_disturb != after.do_not_disturb {
+        add("DoNotDisturb", Box::new(after.do_not_disturb));
+    }
+    if before.notification_center != after.notification_center {
+        add(
+            "NotificationCenterOpen",
+            Box::new(after.notification_center),
+        );
+    }
+    if before.count != after.count {
+        add("Count", Box::new(after.count));
+    }
+    if before.paused != after.paused {
+        add("Paused", Box::new(after.paused));
+    }
+    if changed.is_empty() {
+        return None;
+    }
+    let signal = PropertiesPropertiesChanged {
+        interface_name: "org.freedesktop.Notifications".to_string(),
+        changed_properties: changed,
+        invalidated_properties: Vec::new(),
+    };
+    Some(signal.to_emit_message(path))
+}
+
+/// Runs a change to the wrapper and pushes a PropertiesChanged signal if any property changed.
+fn with_properties_changed<R>(
+    ctx: &mut Context,
+    server: &mut NotificationWrapper,
+    change: impl FnOnce(&mut NotificationWrapper) -> R,
+) -> R {
+    let before = server.state();
+    let result = change(server);
+    if let Some(signal) = properties_changed(ctx.path(), before, server.state()) {
+        ctx.push_msg(signal);
+    }
+    result
+}
+
+/// The bus name of the notification spec, owned by the daemon unless another one is given.
+pub const DEFAULT_BUS_NAME: &str = "org.freedesktop.Notifications";
+
+/// Checks a bus name given on the command line.
+pub fn parse_bus_name(name: &str) -> Result<String, String> {
+    dbus::strings::BusName::new(name)?;
+    Ok(name.to_string())
+}
+
+/// Options of the daemon that are given on the command line instead of the config file.
+#[derive(Clone)]
+pub struct DaemonOptions {
+    /// Replaces a notification daemon that already owns the bus name.
+    pub replace: bool,
+    /// Prints every notification that is received or closed.
+    pub verbose: bool,
+    /// File the notification history is kept in, it is only kept in memory without one.
+    pub history: Option<PathBuf>,
+    /// Bus name owned by the daemon.
+    pub bus_name: String,
+    /// Name reported by GetServerInformation, the binary running the daemon should set its own.
+    pub name: String,
+    /// Version reported by GetServerInformation, the binary running the daemon should set its own.
+    pub version: String,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            replace: false,
+            verbose: false,
+            history: None,
+            bus_name: DEFAULT_BUS_NAME.to_string(),
+            name: "oxinoti".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// How often the retention is applied besides on every new notification.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// How long the daemon waits for messages before it checks on anything else.
+const PROCESS_TIMEOUT: Duration = Duration::from_millis(1000);
+/// The shorter wait while the sink may close notifications, so timeouts are not delayed by much.
+const SINK_CLOSE_TIMEOUT: Duration = Duration::from_millis(50);
+
+pub struct NotificationServer {
+    wrapper: Arc<Mutex<NotificationWrapper>>,
+    /// Notifications the sink closed on its own, None if the sink does not keep its closer.
+    sink_closed: Option<mpsc::Receiver<(u32, CloseReason)>>,
+}
+
+impl NotificationServer {
+    pub fn create(sink: impl NotificationSink + 'static, config: Arc<Config>) -> Self {
+        let (closed, sink_closed) = mpsc::channel();
+        sink.connect_closer(SinkCloser { closed });
+        let keeps_closer = !matches!(
+            sink_closed.try_recv(),
+            Err(mpsc::TryRecvError::Disconnected)
+        );
+        Self {
+            wrapper: Arc::new(Mutex::new(NotificationWrapper::create(sink, config))),
+            sink_closed: keeps_closer.then_some(sink_closed),
+        }
+    }
+
+    /// Closes the notifications the sink closed on its own and sends the signals for them.
+    fn close_from_sink(&self, c: &Connection) {
+        let Some(sink_closed) = &self.sink_closed else {
+            return;
+        };
+        let path = dbus::Path::from("/org/freedesktop/Notifications");
+        for (id, reason) in sink_closed.try_iter() {
+            let mut server = self.wrapper.lock().unwrap();
+            let before = server.state();
+            if server.close_notification(id, reason) {
+                let message = Message::signal(
+                    &path,
+                    &"org.freedesktop.Notifications".into(),
+                    &"NotificationClosed".into(),
+                )
+                .append2(id, reason.to_u32());
+                let _ = c.send(message);
+            }
+            if let Some(message) = properties_changed(&path, before, server.state()) {
+                let _ = c.send(message);
+            }
+        }
+    }
+
+    /// Applies the retention and sends PropertiesChanged if the stored notifications changed.
+    fn sweep(&self, c: &Connection) {
+        let path = dbus::Path::from("/org/freedesktop/Notifications");
+        let mut server = self.wrapper.lock().unwrap();
+        let before = server.state();
+        server.sweep();
+        if let Some(message) = properties_changed(&path, before, server.state()) {
+            let _ = c.send(message);
+        }
+    }
+
+    /// Runs the daemon on the session bus.
+    /// Returns once another daemon replaced this one, or with an error if the bus name is taken.
+    pub fn run(&mut self, options: DaemonOptions) -> Result<(), String> {
+        let connection = Connection::new_session()
+            .map_err(|error| format!("Could not connect to the session bus: {}", error))?;
+        self.serve(connection, options)
+    }
+
+    /// Runs the daemon on an existing connection, e.g. to a private bus.
+    pub fn serve(&mut self, c: Connection, options: DaemonOptions) -> Result<(), String> {
+        self.wrapper.lock().unwrap().verbose = options.verbose;
+        // always allow replacement, so other daemons can take over with their own replace flag
+        let bus_name = options.bus_name.clone();
+        match c.request_name(bus_name.as_str(), true, options.replace, true) {
+            Ok(RequestNameReply::PrimaryOwner) | Ok(RequestNameReply::AlreadyOwner) => (),
+            Ok(_) => {
+                let owner = "is already owned by another notification daemon";
+                return Err(format!(
+                    "{} {}, use --replace to replace it",
+                    bus_name, owner
+                ));
+            }
+            Err(error) => return Err(format!("Could not request {}: {}", bus_name, error)),
+        }
+        // opening compacts the file, so only the daemon that owns the name may touch it
+        if let Some(path) = &options.history {
+            match History::open(path) {
+                Ok(history) => {
+                    let mut wrapper = self.wrapper.lock().unwrap();
+                    wrapper.load_history(history);
+                    wrapper.sweep();
+                }
+                Err(error) => println!("{}, the history is only kept in memory", error),
+            }
+        }
+        let lost = Arc::new(AtomicBool::new(false));
+        let lost_name = lost.clone();
+        let watched_name = bus_name.clone();
+        c.add_match(
+            MatchRule::new_signal("org.freedesktop.DBus", "NameLost"),
+            move |(name,): (String,), _, _| {
+                if name == watched_name {
+                    lost_name.store(true, Ordering::SeqCst);
+                }
+                true
+            },
+        )
+        .map_err(|error| format!("Could not watch {}: {}", bus_name, error))?;
+        let server_information = get_server_information(&options);
+        let mut cr = dbus_crossroads::Crossroads::new();
+        let token = cr.register("org.freedesktop.Notifications", |c| {
+            let action_invoked = c
+                .signal::<(u32, String), _>("ActionInvoked", ("id", "action_key"))
+                .msg_fn();
+            let activation_token = c
+                .signal::<(u32, String), _>("ActivationToken", ("id", "activation_token"))
+                .msg_fn();
+            let inline_replied = c
+                .signal::<(u32, String), _>("NotificationReplied", ("id", "text"))
+                .msg_fn();
+            let notification_closed = Arc::new(
+                c.signal::<(u32, u32), _>("NotificationClosed", ("id", "reason"))
+                    .msg_fn(),
+            );
+            let closed_on_close = notification_closed.clone();
+            let closed_on_remove = notification_closed.clone();
+            let closed_on_notify = notification_closed.clone();
+            c.method(
+                "Notify",
+                (
+                    "app_name",
+                    "replaces_id",
+                    "app_icon",
+                    "summary",
+                    "body",
+                    "actions",
+                    "hints",
+                    "expire_timeout",
+                ),
+                ("id",),
+                move |ctx: &mut Context,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (
+                    app_name,
+                    replaces_id,
+                    app_icon,
+                    summary,
+                    body,
+                    actions,
+                    hints,
+                    expire_timeout,
+                ): (
+                    String,
+                    u32,
+                    String,
+                    String,
+                    String,
+                    Vec<String>,
+                    arg::PropMap,
+                    i32,
+                )| {
+                    let has_urgency = hints.contains_key("urgency");
+                    let desktop_app = string_hint(&hints, "desktop-entry")
+                        .and_then(|desktop_entry| lookup_desktop_entry(serverref, &desktop_entry));
+                    let mut server = serverref.lock().unwrap();
+                    let mut notification = Notification::create(
+                        app_name,
+                        replaces_id,
+                        app_icon,
+                        summary,
+                        body,
+                        actions,
+                        hints,
+                        expire_timeout,
+                        server.verbose,
+                    );
+                    let config = server.config.clone();
+                    notification.apply_category_config(&config, has_urgency);
+                    let outcome = rules::apply_rules(&config.rules, &mut notification);
+                    notification.id = server.next_id(replaces_id);
+                    let id = notification.id;
+                    // the commands are spawned once the lock is released
+                    let command_args = [
+                        notification.app_name.clone(),
+                        notification.summary.clone(),
+                        notification.body.clone(),
+                    ];
+                    if outcome.drop {
+                        if server.verbose {
+                            println!("Dropping notification {} because of a rule", id);
+                        }
+                        // a dropped replacement keeps the notification it would have replaced
+                        if !server.contains(id) {
+                            let reason = CloseReason::Undefined.to_u32();
+                            ctx.push_msg(closed_on_notify(ctx.path(), &(id, reason)));
+                        }
+                        drop(server);
+                        run_rule_commands(&outcome.commands, &command_args);
+                        return Ok((id,));
+                    }
+                    notification.desktop_app = desktop_app;
+                    with_properties_changed(ctx, &mut server, |server| {
+                        server.add_notification(&mut notification)
+                    });
+                    let show_popup = (outcome.ignore_dnd
+                        || urgency_should_ignore_dnd(
+                            server.do_not_disturb,
+                            config.dnd_override,
+                            &notification.urgency,
+                        ))
+                        && !server.notification_center
+                        && !outcome.hide_popup;
+                    let mut sound = None;
+                    if show_popup && server.paused {
+                        server.queued.push(notification);
+                    } else if show_popup {
+                        sound = SoundRequest::create(&notification, &config);
+                        server.sink.show(notification);
+                    } else if !notification.transient {
+                        ctx.push_msg(notification_center_notify(notification));
+                    } else if server.close_notification(id, CloseReason::Undefined) {
+                        // a transient notification that is not shown is gone right away
+                        let reason = CloseReason::Undefined.to_u32();
+                        ctx.push_msg(closed_on_notify(ctx.path(), &(id, reason)));
+                    }
+                    drop(server);
+                    run_rule_commands(&outcome.commands, &command_args);
+                    if let Some(sound) = sound {
+                        sound.play();
+                    }
+                    Ok((id,))
+                },
+            );
+            c.method(
+                "CloseNotification",
+                ("id",),
+                ("response",),
+                move |ctx: &mut Context,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (id,): (u32,)| {
+                    let reason = CloseReason::Closed;
+                    let mut server = serverref.lock().unwrap();
+                    if with_properties_changed(ctx, &mut server, |server| {
+                        server.close_notification(id, reason)
+                    }) {
+                        let signal = closed_on_close(ctx.path(), &(id, reason.to_u32()));
+                        ctx.push_msg(signal);
+                    }
+                    Ok(("ok",))
+                },
+            );
+            c.method(
+                "RemoveNotification",
+                ("id", "reason"),
+                ("response",),
+                move |ctx: &mut Context,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (id, reason): (u32, u32)| {
+                    let reason = CloseReason::from_u32(reason);
+                    let mut server = serverref.lock().unwrap();
+                    if with_properties_changed(ctx, &mut server, |server| {
+                        server.close_notification(id, reason)
+                    }) {
+                        let signal = closed_on_remove(ctx.path(), &(id, reason.to_u32()));
+                        ctx.push_msg(signal);
+                    }
+                    Ok(("ok",))
+                },
+            );
+            c.method(
+                "GetAllNotifications",
+                (),
+                ("notifications",),
+                move |_, serverref: &mut Arc<Mutex<NotificationWrapper>>, ()| {
+                    let mut notifications = Vec::new();
+                    for notification in serverref.lock().unwrap().get_all_notifications().iter() {
+                        let image_data = notification
+                            .image_data
+                            .clone()
+                            .unwrap_or_else(ImageData::empty)
+                            .into_tuple();
+                        notifications.push((
+                            notification.app_name.clone(),
+                            notification.id,
+                            notification.app_icon.clone(),
+                            notification.summary.clone(),
+                            notification.body.clone(),
+                            notification.actions.clone(),
+                            notification.expire_timeout.clone(),
+                            notification.urgency.clone().to_i32(),
+                            notification
+                                .image_path
+                                .clone()
+                                .unwrap_or_else(|| "".to_string()),
+                            notification.progress.clone().unwrap_or_else(|| -1),
+                            image_data,
+                            notification.category.clone().unwrap_or_default(),
+                        ));
+                    }
+                    Ok((notifications,))
+                },
+            );
+            c.method(
+                "QueryHistory",
+                ("filters", "offset", "limit"),
+                ("records", "total"),
+                move |_,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (filters, offset, limit): (arg::PropMap, u32, u32)| {
+                    let filter = history_filter(&filters)?;
+                    let server = serverref.lock().unwrap();
+                    let (records, total) =
+                        server
+                            .history
+                            .query(&filter, offset as usize, limit as usize);
+                    let records: Vec<arg::PropMap> = records
+                        .into_iter()
+                        .map(|record| {
+                            let has_image = server
+                                .notifications
+                                .get(&record.id)
+                                .is_some_and(|notification| notification.image_data.is_some());
+                            history_record_props(record, has_image)
+                        })
+                        .collect();
+                    Ok((records, total as u32))
+                },
+            );
+            c.method(
+                "GetNotificationImage",
+                ("id",),
+                ("image",),
+                move |_, serverref: &mut Arc<Mutex<NotificationWrapper>>, (id,): (u32,)| {
+                    let server = serverref.lock().unwrap();
+                    let notification = server
+                        .notifications
+                        .get(&id)
+                        .ok_or_else(|| MethodErr::invalid_arg(&id))?;
+                    let image_data = notification
+                        .image_data
+                        .clone()
+                        .unwrap_or_else(ImageData::empty);
+                    Ok((image_data.into_tuple(),))
+                },
+            );
+            c.method(
+                "MarkRead",
+                ("ids",),
+                (),
+                move |_, serverref: &mut Arc<Mutex<NotificationWrapper>>, (ids,): (Vec<u32>,)| {
+                    serverref.lock().unwrap().mark_read(&ids);
+                    Ok(())
+                },
+            );
+            c.method(
+                "RemoveAllNotifications",
+                (),
+                ("response",),
+                move |ctx: &mut Context, serverref: &mut Arc<Mutex<NotificationWrapper>>, ()| {
+                    let mut server = serverref.lock().unwrap();
+                    let ids = with_properties_changed(ctx, &mut server, |server| {
+                        server.clear_all_notifications()
+                    });
+                    for id in ids {
+                        let signal =
+                            notification_closed(ctx.path(), &(id, CloseReason::Undefined.to_u32()));
+                        ctx.push_msg(signal);
+                    }
+                    Ok(("ok",))
+                },
+            );
+            c.method(
+                "GetServerInformation",
+                (),
+                ("name", "vendor", "version", "spec_version"),
+                move |_, _, ()| Ok(server_information.clone()),
+            );
+            c.method(
+                "GetCapabilities",
+                (),
+                ("capabilities",),
+                move |_, serverref: &mut Arc<Mutex<NotificationWrapper>>, ()| {
+                    let server = serverref.lock().unwrap();
+                    Ok((get_capabilities(
+                        &server.config,
+                        server.persists_notifications(),
+                    ),))
+                },
+            );
+            c.method(
+                "ReloadConfig",
+                (),
+                (),
+                move |_, serverref: &mut Arc<Mutex<NotificationWrapper>>, ()| {
+                    serverref.lock().unwrap().reload_config();
+                    Ok(())
+                },
+            );
+            c.method(
+                "DoNotDisturb",
+                (),
+                ("status",),
+                move |ctx: &mut Context, serverref: &mut Arc<Mutex<NotificationWrapper>>, ()| {
+                    let mut server = serverref.lock().unwrap();
+                    let result = with_properties_changed(ctx, &mut server, |server| {
+                        server.toggle_do_not_disturb()
+                    });
+                    Ok((result,))
+                },
+            );
+            c.method(
+                "SetDoNotDisturb",
+                ("status",),
+                (),
+                move |ctx: &mut Context,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (status,): (bool,)| {
+                    let mut server = serverref.lock().unwrap();
+                    with_properties_changed(ctx, &mut server, |server| {
+                        server.set_do_not_disturb(status)
+                    });
+                    Ok(())
+                },
+            );
+            c.method(
+                "ToggleNotificationCenter",
+                (),
+                ("result",),
+                move |ctx: &mut Context, serverref: &mut Arc<Mutex<NotificationWrapper>>, ()| {
+                    let mut server = serverref.lock().unwrap();
+                    let res = with_properties_changed(ctx, &mut server, |server| {
+                        server.toggle_notification_center()
+                    });
+                    Ok((res,))
+                },
+            );
+            c.method(
+                "SetNotificationCenterOpen",
+                ("open",),
+                (),
+                move |ctx: &mut Context,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (open,): (bool,)| {
+                    let mut server = serverref.lock().unwrap();
+                    with_properties_changed(ctx, &mut server, |server| {
+                        server.set_notification_center(open)
+                    });
+                    Ok(())
+                },
+            );
+            c.method(
+                "SetPaused",
+                ("paused",),
+                (),
+                move |ctx: &mut Context,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (paused,): (bool,)| {
+                    let mut server = serverref.lock().unwrap();
+                    with_properties_changed(ctx, &mut server, |server| server.set_paused(paused));
+                    Ok(())
+                },
+            );
+            c.property::<bool, _>("DoNotDisturb")
+                .emits_changed_true()
+                .get(|_, serverref| Ok(serverref.lock().unwrap().do_not_disturb))
+                .set(|_, serverref, status| {
+                    let mut server = serverref.lock().unwrap();
+                    let changed = server.do_not_disturb != status;
+                    server.set_do_not_disturb(status);
+                    Ok(changed.then_some(status))
+                });
+            c.property::<bool, _>("NotificationCenterOpen")
+                .emits_changed_true()
+                .get(|_, serverref| Ok(serverref.lock().unwrap().notification_center))
+                .set(|_, serverref, open| {
+                    let mut server = serverref.lock().unwrap();
+                    let changed = server.notification_center != open;
+                    server.set_notification_center(open);
+                    Ok(changed.then_some(open))
+                });
+            c.property::<u32, _>("Count")
+                .get(|_, serverref| Ok(serverref.lock().unwrap().state().count));
+            c.property::<bool, _>("Paused")
+                .emits_changed_true()
+                .get(|_, serverref| Ok(serverref.lock().unwrap().paused))
+                .set(|_, serverref, paused| {
+                    let mut server = serverref.lock().unwrap();
+                    let changed = server.paused != paused;
+                    server.set_paused(paused);
+                    Ok(changed.then_some(paused))
+                });
+            c.method(
+                "InvokeAction",
+                ("id", "action"),
+                (),
+                move |ctx: &mut Context,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (id, action): (u32, String)| {
+                    let mut server = serverref.lock().unwrap();
+                    server.record_action(id, &action);
+                    // the token has to arrive first, so the application can use it when handling the action
+                    if let Some(token) = server.sink.activation_token(id) {
+                        let signal = activation_token(ctx.path(), &(id, token));
+                        ctx.push_msg(signal);
+                    }
+                    let signal = action_invoked(ctx.path(), &(id, action));
+                    ctx.push_msg(signal);
+                    Ok(())
+                },
+            );
+            c.method(
+                "InlineReply",
+                ("id", "text"),
+                (),
+                move |ctx: &mut Context,
+                      serverref: &mut Arc<Mutex<NotificationWrapper>>,
+                      (id, text): (u32, String)| {
+                    serverref.lock().unwrap().record_action(id, "inline-reply");
+                    let signal = inline_replied(ctx.path(), &(id, text));
+                    ctx.push_msg(signal);
+                    Ok(())
+                },
+            );
+        });
+        cr.insert(
+            "/org/freedesktop/Notifications",
+            &[token],
+            self.wrapper.clone(),
+        );
+        c.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, conn| {
+                let _ = cr.handle_message(message, conn);
+                true
+            }),
+        );
+        let timeout = if self.sink_closed.is_some() {
+            SINK_CLOSE_TIMEOUT
+        } else {
+            PROCESS_TIMEOUT
+        };
+        let mut last_sweep = Instant::now();
+        while !lost.load(Ordering::SeqCst) {
+            c.process(timeout)
+                .map_err(|error| format!("Lost the connection to the bus: {}", error))?;
+            self.close_from_sink(&c);
+            if last_sweep.elapsed() >= SWEEP_INTERVAL {
+                last_sweep = Instant::now();
+                self.sweep(&c);
+            }
+        }
+        println!(
+            "{} was taken over by another daemon, shutting down",
+            bus_name
+        );
+        Ok(())
+    }
+}
+
+/// Creates the Notify call that forwards a notification to the notification center.
+/// No reply is expected, so it does not matter whether a notification center is running.
+fn notification_center_notify(notification: Notification) -> Message {
+    let image_data = notification
+        .image_data
+        .unwrap_or_else(ImageData::empty)
+        .into_tuple();
+    let mut message = Message::method_call(
+        &"org.freedesktop.NotificationCenter".into(),
+        &"/org/freedesktop/NotificationCenter".into(),
+        &"org.freedesktop.NotificationCenter".into(),
+        &"Notify".into(),
+    );
+    message.append_all((
+        notification.app_name,
+        notification.id,
+        notification.app_icon,
+        notification.summary,
+        notification.body,
+        notification.actions,
+        notification.expire_timeout,
+        notification.urgency.to_i32(),
+        notification.image_path.unwrap_or_default(),
+        notification.progress.unwrap_or(-1),
+        image_data,
+    ));
+    message.set_no_reply(true);
+    message
+}
+
+/// Reads the a{sv} filters of QueryHistory.
+fn history_filter(filters: &arg::PropMap) -> Result<HistoryFilter, MethodErr> {
+    let mut filter = HistoryFilter::default();
+    for (name, value) in filters.iter() {
+        let invalid = || MethodErr::invalid_arg(name);
+        let time = || {
+            value
+                .as_u64()
+                .or_else(|| value.as_i64().and_then(|time| u64::try_from(time).ok()))
+                .ok_or_else(invalid)
+        };
+        let text = || value.as_str().map(str::to_string).ok_or_else(invalid);
+        match name.as_str() {
+            "app_name" => filter.app_name = Some(text()?),
+            "category" => filter.category = Some(text()?),
+            "text" => filter.text = Some(text()?),
+            "urgency" => filter.urgency = Some(value.as_i64().ok_or_else(invalid)? as i32),
+            "since" => filter.since = Some(time()?),
+            "until" => filter.until = Some(time()?),
+            "read" => filter.read = Some(*cast::<bool>(&value.0).ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(filter)
+}
+
+/// Creates the a{sv} of a record returned by QueryHistory.
+fn history_record_props(record: &HistoryRecord, has_image: bool) -> arg::PropMap {
+    let mut props = arg::PropMap::new();
+    let mut add = |name: &str, value: Box<dyn RefArg>| {
+        props.insert(name.to_string(), Variant(value));
+    };
+    add("id", Box::new(record.id));
+    add("received", Box::new(record.received));
+    add("app_name", Box::new(record.app_name.clone()));
+    add("app_icon", Box::new(record.app_icon.clone()));
+    add("summary", Box::new(record.summary.clone()));
+    add("body", Box::new(record.body.clone()));
+    add("actions", Box::new(record.actions.clone()));
+    add("urgency", Box::new(record.urgency));
+    add(
+        "category",
+        Box::new(record.category.clone().unwrap_or_default()),
+    );
+    add(
+        "image_path",
+        Box::new(record.image_path.clone().unwrap_or_default()),
+    );
+    add("has_image", Box::new(has_image));
+    add("close_reason", Box::new(record.close_reason.unwrap_or(0)));
+    add(
+        "action",
+        Box::new(record.action.clone().unwrap_or_default()),
+    );
+    add("read", Box::new(record.read));
+    add("stored", Box::new(record.stored));
+    props
+}
+
+/// Converts the expire_timeout of a notification to milliseconds, 0 means the notification never expires.
+/// -1 and other negative values fall back to the timeout of the config.
+pub fn timeout_millis(expire_timeout: i32, config: &Config) -> u64 {
+    match expire_timeout {
+        0 => 0,
+        timeout if timeout > 0 => timeout as u64,
+        _ => config.timeout * 1000,
+    }
+}
+
+/// Looks up the desktop entry of a notification,
+/// the data directories are only scanned on a cache miss and without holding the lock.
+fn lookup_desktop_entry(
+    wrapper: &Mutex<NotificationWrapper>,
+    desktop_entry: &str,
+) -> Option<DesktopEntry> {
+    if let Some(entry) = wrapper
+        .lock()
+        .unwrap()
+        .desktop_entries
+        .cached(desktop_entry)
+    {
+        return entry;
+    }
+    let entry = find_desktop_entry(desktop_entry);
+    wrapper
+        .lock()
+        .unwrap()
+        .desktop_entries
+        .insert(desktop_entry, entry.clone());
+    entry
+}
+
+/// Runs the commands of the matching rules with the app name, summary and body as arguments.
+fn run_rule_commands(commands: &[String], args: &[String; 3]) {
+    for command in commands {
+        if spawn_command(command, args).is_err() {
+            println!("Could not run {}", command);
+        }
+    }
+}
+
+/// Runs a command line like "pw-play --volume 0.5" with additional arguments without waiting for it.
+pub fn spawn_command<I, S>(command: &str, args: I) -> io::Result<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut parts = command.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+    let mut child = Command::new(program)
+        .args(parts)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    thread::spawn(move || {
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+fn string_hint(hints: &arg::PropMap, name: &str) -> Option<String> {
+    hints
+        .get(name)
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_string())
+}
+
+fn bool_hint(hints: &arg::PropMap, name: &str) -> bool {
+    hints
+        .get(name)
+        .and_then(|value| cast::<bool>(&value.0).copied())
+        .unwrap_or(false)
+}
+
+/// Returns name, vendor, version and the implemented version of the notification spec.
+pub fn get_server_information(options: &DaemonOptions) -> (String, String, String, String) {
+    (
+        options.name.clone(),
+        "dashie".to_string(),
+        options.version.clone(),
+        "1.2".to_string(),
+    )
+}
+
+/// Returns the capabilities that are implemented by the popups and the daemon, optional features only when enabled.
+/// inline-reply is not part of the spec, it is the extension of KDE Plasma that is answered with NotificationReplied.
+pub fn get_capabilities(config: &Config, persistent: bool) -> Vec<String> {
+    let mut capabilities = vec![
+        "action-icons",
+        "actions",
+        "body",
+        "body-hyperlinks",
+        "body-images",
+        "body-markup",
+        "icon-static",
+        "inline-reply",
+    ];
+    if persistent {
+        capabilities.push("persistence");
+    }
+    if config.sound {
+        capabilities.push("sound");
+    }
+    capabilities
+        .into_iter()
+        .map(|capability| capability.to_string())
+        .collect()
+}
+
+pub fn urgency_should_ignore_dnd(
+    dnd_enabled: bool,
+    dnd_ignore_threshold: i32,
+    urgency: &Urgency,
+) -> bool {
+    if !dnd_enabled {
+        return true;
+    }
+    let necessary_urgency = match dnd_ignore_threshold {
+        0 => Some(Urgency::Low),
+        1 => Some(Urgency::Normal),
+        2 => Some(Urgency::Urgent),
+        _ => None,
+    };
+    if necessary_urgency.is_none() || urgency < &necessary_urgency.unwrap() {
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Remembers what the wrapper hands to the presenter.
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        shown: Arc<Mutex<Vec<u32>>>,
+        closed: Arc<Mutex<Vec<(u32, CloseReason)>>>,
+    }
+
+    impl NotificationSink for RecordingSink {
+        fn show(&self, notification: Notification) {
+            self.shown.lock().unwrap().push(notification.id);
+        }
+        fn close(&self, id: u32, reason: CloseReason) {
+            self.closed.lock().unwrap().push((id, reason));
+        }
+    }
+
+    fn wrapper() -> (NotificationWrapper, RecordingSink) {
+        let sink = RecordingSink::default();
+        let wrapper = NotificationWrapper::create(sink.clone(), Arc::new(parse_config("")));
+        (wrapper, sink)
+    }
+
+    fn notification(hints: arg::PropMap) -> Notification {
+        Notification::create(
+            "app".to_string(),
+            0,
+            String::new(),
+            "summary".to_string(),
+            "body".to_string(),
+            Vec::new(),
+            hints,
+            -1,
+            false,
+        )
+    }
+
+    fn add(wrapper: &mut NotificationWrapper, mut notification: Notification) -> u32 {
+        notification.id = wrapper.next_id(notification.replaces_id);
+        wrapper.add_notification(&mut notification);
+        notification.id
+    }
+
+    #[test]
+    fn server_information_comes_from_the_options() {
+        let options = DaemonOptions {
+            name: "custom".to_string(),
+            version: "9.9.9".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_server_information(&options),
+            (
+                "custom".to_string(),
+                "dashie".to_string(),
+                "9.9.9".to_string(),
+                "1.2".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn hints_are_parsed() {
+        let mut hints = arg::PropMap::new();
+        hints.insert("urgency".to_string(), Variant(Box::new(2u8)));
+        hints.insert(
+            "image_path".to_string(),
+            Variant(Box::new("/a.png".to_string())),
+        );
+        hints.insert("progress".to_string(), Variant(Box::new(150i32)));
+        hints.insert("resident".to_string(), Variant(Box::new(true)));
+        hints.insert("category".to_string(), Variant(Box::new("im".to_string())));
+        let notification = notification(hints);
+        assert!(notification.urgency == Urgency::Urgent);
+        assert_eq!(notification.image_path.as_deref(), Some("/a.png"));
+        assert_eq!(notification.progress, Some(100));
+        assert!(notification.resident);
+        assert!(!notification.transient);
+        assert_eq!(notification.category.as_deref(), Some("im"));
+    }
+
+    #[test]
+    fn ids_skip_zero_and_existing_notifications() {
+        let (mut wrapper, _) = wrapper();
+        let first = add(&mut wrapper, notification(arg::PropMap::new()));
+        assert_eq!(first, 1);
+        wrapper.last_notification_id = u32::MAX;
+        assert_eq!(wrapper.next_id(0), first + 1);
+        // only ids of existing notifications are reused
+        assert_eq!(wrapper.next_id(first), first);
+        assert_ne!(wrapper.next_id(42), 42);
+    }
+
+    #[test]
+    fn expired_notifications_stay_until_closed() {
+        let (mut wrapper, sink) = wrapper();
+        let id = add(&mut wrapper, notification(arg::PropMap::new()));
+        assert!(wrapper.close_notification(id, CloseReason::Expired));
+        assert!(!wrapper.close_notification(id, CloseReason::Expired));
+        assert_eq!(wrapper.get_all_notifications().len(), 1);
+        assert!(!wrapper.close_notification(id, CloseReason::Dismissed));
+        assert!(wrapper.get_all_notifications().is_empty());
+        assert_eq!(
+            *sink.closed.lock().unwrap(),
+            vec![(id, CloseReason::Expired)]
+        );
+    }
+
+    #[test]
+    fn transient_notifications_are_not_stored() {
+        let (mut wrapper, sink) = wrapper();
+        let mut hints = arg::PropMap::new();
+        hints.insert("transient".to_string(), Variant(Box::new(true)));
+        let id = add(&mut wrapper, notification(hints));
+        assert!(wrapper.get_all_notifications().is_empty());
+        assert!(wrapper.history.is_empty());
+        assert!(wrapper.close_notification(id, CloseReason::Closed));
+        assert!(!wrapper.close_notification(id, CloseReason::Closed));
+        assert_eq!(
+            *sink.closed.lock().unwrap(),
+            vec![(id, CloseReason::Closed)]
+        );
+    }
+
+    #[test]
+    fn paused_notifications_are_shown_on_resume() {
+        let (mut wrapper, sink) = wrapper();
+        wrapper.set_paused(true);
+        let shown = add(&mut wrapper, notification(arg::PropMap::new()));
+        let closed = add(&mut wrapper, notification(arg::PropMap::new()));
+        for id in [shown, closed] {
+            let mut notification = notification(arg::PropMap::new());
+            notification.id = id;
+            wrapper.queued.push(notification);
+        }
+        wrapper.close_notification(closed, CloseReason::Closed);
+        assert!(sink.shown.lock().unwrap().is_empty());
+        wrapper.set_paused(false);
+        assert_eq!(*sink.shown.lock().unwrap(), vec![shown]);
+    }
+
+    #[test]
+    fn timeouts_fall_back_to_the_config() {
+        let config = parse_config("");
+        assert_eq!(timeout_millis(0, &config), 0);
+        assert_eq!(timeout_millis(1500, &config), 1500);
+        assert_eq!(timeout_millis(-1, &config), config.timeout * 1000);
+    }
+}
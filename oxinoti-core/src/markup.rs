@@ -0,0 +1,334 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+/// Notification markup converted to pango markup.
+pub struct Markup {
+    /// Pango markup that can be passed to set_markup.
+    pub text: String,
+    /// Sources of all images in order, file:// is stripped from local files.
+    pub images: Vec<String>,
+}
+
+/// Parses the markup subset of the notification spec: b, i, u, a href and img src alt.
+/// br is accepted as a line break, any other tag is shown as text.
+/// Malformed markup like unclosed or mismatched tags falls back to plain text.
+pub fn parse_markup(input: &str) -> Markup {
+    match convert(input) {
+        Some(markup) => markup,
+        None => Markup {
+            text: escape(input),
+            images: Vec::new(),
+        },
+    }
+}
+
+fn convert(input: &str) -> Option<Markup> {
+    let mut text = String::new();
+    let mut images = Vec::new();
+    let mut open_tags: Vec<&str> = Vec::new();
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '<' => {
+                let tag = parse_tag(rest);
+                if tag.is_none() {
+                    text.push_str("&lt;");
+                    rest = &rest[1..];
+                    continue;
+                }
+                let (tag, length) = tag.unwrap();
+                rest = &rest[length..];
+                match tag {
+                    // an empty b, i, u or a has nothing to format
+                    Tag::Open("b" | "i" | "u" | "a", _, true) => (),
+                    Tag::Open(name, attributes, _) => match name {
+                        "b" | "i" | "u" => {
+                            text.push_str(&format!("<{}>", name));
+                            open_tags.push(name);
+                        }
+                        "a" => {
+                            let href = attribute(&attributes, "href").unwrap_or_default();
+                            text.push_str(&format!("<a href=\"{}\">", escape(&href)));
+                            open_tags.push(name);
+                        }
+                        "img" => {
+                            if let Some(src) = attribute(&attributes, "src") {
+                                images
+                                    .push(src.strip_prefix("file://").unwrap_or(&src).to_string());
+                            }
+                            if let Some(alt) = attribute(&attributes, "alt") {
+                                text.push_str(&escape(&alt));
+                            }
+                        }
+                        "br" => text.push('\n'),
+                        _ => (),
+                    },
+                    Tag::Close(name) => match name {
+                        "img" | "br" => (),
+                        _ => {
+                            if open_tags.pop() != Some(name) {
+                                return None;
+                            }
+                            text.push_str(&format!("</{}>", name));
+                        }
+                    },
+                }
+            }
+            '&' => {
+                let length = entity_length(rest);
+                if length > 0 {
+                    text.push_str(&rest[..length]);
+                    rest = &rest[length..];
+                } else {
+                    text.push_str("&amp;");
+                    rest = &rest[1..];
+                }
+            }
+            _ => {
+                text.push_str(&escape(&rest[..c.len_utf8()]));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !open_tags.is_empty() {
+        return None;
+    }
+    Some(Markup { text, images })
+}
+
+enum Tag<'a> {
+    /// The name, the attributes and whether the tag closes itself like <br/>.
+    Open(&'a str, Vec<(String, String)>, bool),
+    Close(&'a str),
+}
+
+/// Parses a supported tag at the start of the input and returns it with its length.
+/// Returns None for anything that is not a supported tag, which is then treated as text.
+fn parse_tag(input: &str) -> Option<(Tag<'_>, usize)> {
+    let end = tag_end(input)?;
+    let inner = &input[1..end];
+    let (closing, inner) = match inner.strip_prefix('/') {
+        Some(inner) => (true, inner),
+        None => (false, inner),
+    };
+    let (self_closing, inner) = match inner.strip_suffix('/') {
+        Some(inner) => (true, inner),
+        None => (false, inner),
+    };
+    let name_end = inner
+        .find(|c: char| c.is_whitespace())
+        .unwrap_or(inner.len());
+    let name = &inner[..name_end];
+    let name = ["b", "i", "u", "a", "img", "br"]
+        .into_iter()
+        .find(|supported| supported.eq_ignore_ascii_case(name))?;
+    if closing {
+        return Some((Tag::Close(name), end + 1));
+    }
+    let attributes = parse_attributes(&inner[name_end..])?;
+    Some((Tag::Open(name, attributes, self_closing), end + 1))
+}
+
+/// Returns the position of the > that ends the tag at the start of the input.
+/// A > inside a quoted attribute value like href="a>b" does not end the tag.
+fn tag_end(input: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, c) in input.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '>') => return Some(index),
+            // a new tag starts before this one ended, so this is not a tag
+            (None, '<') => return None,
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Parses attributes like href="value", single quotes and unquoted values are accepted as well.
+fn parse_attributes(mut input: &str) -> Option<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    loop {
+        input = input.trim_start();
+        if input.is_empty() {
+            return Some(attributes);
+        }
+        let name_end = input
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(input.len());
+        let name = input[..name_end].to_ascii_lowercase();
+        input = input[name_end..].trim_start();
+        let value;
+        if let Some(rest) = input.strip_prefix('=') {
+            let rest = rest.trim_start();
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'');
+            if let Some(quote) = quote {
+                let value_end = rest[1..].find(quote)?;
+                value = unescape(&rest[1..value_end + 1]);
+                input = &rest[value_end + 2..];
+            } else {
+                let value_end = rest.find(|c: char| c.is_whitespace()).unwrap_or(rest.len());
+                value = unescape(&rest[..value_end]);
+                input = &rest[value_end..];
+            }
+        } else {
+            value = String::new();
+        }
+        attributes.push((name, value));
+    }
+}
+
+fn attribute(attributes: &[(String, String)], name: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.clone())
+}
+
+/// Returns the length of a valid entity at the start of the input, or 0 if there is none.
+fn entity_length(input: &str) -> usize {
+    let end = match input.find(';') {
+        Some(end) => end,
+        None => return 0,
+    };
+    let entity = &input[1..end];
+    let valid = match entity {
+        "amp" | "lt" | "gt" | "quot" | "apos" => true,
+        _ => match entity.strip_prefix('#') {
+            Some(number) => match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => number.parse::<u32>().ok(),
+            }
+            .and_then(char::from_u32)
+            .is_some_and(|c| c != '\0'),
+            None => false,
+        },
+    };
+    if valid {
+        end + 1
+    } else {
+        0
+    }
+}
+
+fn unescape(input: &str) -> String {
+    input
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn escape(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(input: &str) -> String {
+        parse_markup(input).text
+    }
+
+    #[test]
+    fn formatting_tags_can_be_nested() {
+        assert_eq!(text("<b>bold <i>both</i></b>"), "<b>bold <i>both</i></b>");
+        assert_eq!(text("<U>under</u>"), "<u>under</u>");
+        assert_eq!(text("line<br>break<br/>"), "line\nbreak\n");
+    }
+
+    #[test]
+    fn mismatched_or_unclosed_tags_fall_back_to_text() {
+        assert_eq!(
+            text("<b><i>crossed</b></i>"),
+            "&lt;b&gt;&lt;i&gt;crossed&lt;/b&gt;&lt;/i&gt;"
+        );
+        assert_eq!(text("<b>open"), "&lt;b&gt;open");
+        assert_eq!(text("closed</b>"), "closed&lt;/b&gt;");
+    }
+
+    #[test]
+    fn self_closing_tags_are_empty() {
+        assert_eq!(text("<b/>text"), "text");
+        assert_eq!(text("<a href=\"x\"/>text"), "text");
+        assert_eq!(text("<b />text"), "text");
+    }
+
+    #[test]
+    fn unsupported_tags_and_stray_brackets_are_text() {
+        assert_eq!(text("<span>x</span>"), "&lt;span&gt;x&lt;/span&gt;");
+        assert_eq!(text("1 < 2 > 0"), "1 &lt; 2 &gt; 0");
+        assert_eq!(text("<b"), "&lt;b");
+        assert_eq!(text("<<b>x</b>"), "&lt;<b>x</b>");
+    }
+
+    #[test]
+    fn entities_are_kept_and_stray_ampersands_escaped() {
+        assert_eq!(text("&amp; &lt; &#65; &#x41;"), "&amp; &lt; &#65; &#x41;");
+        assert_eq!(text("fish & chips"), "fish &amp; chips");
+        assert_eq!(
+            text("&bogus; &#0; &#xZZ;"),
+            "&amp;bogus; &amp;#0; &amp;#xZZ;"
+        );
+        assert_eq!(text("quote \" and '"), "quote &quot; and &apos;");
+    }
+
+    #[test]
+    fn links_keep_their_href() {
+        assert_eq!(
+            text("<a href=\"https://example.com/?a=1&amp;b=2\">link</a>"),
+            "<a href=\"https://example.com/?a=1&amp;b=2\">link</a>"
+        );
+        assert_eq!(
+            text("<a href=\"x?a>b\">link</a>"),
+            "<a href=\"x?a&gt;b\">link</a>"
+        );
+        assert_eq!(text("<a href='single'>s</a>"), "<a href=\"single\">s</a>");
+        assert_eq!(text("<a href=bare>b</a>"), "<a href=\"bare\">b</a>");
+        assert_eq!(text("<a>none</a>"), "<a href=\"\">none</a>");
+        // an unterminated quote is not a tag
+        assert_eq!(
+            text("<a href=\"x>y</a>"),
+            "&lt;a href=&quot;x&gt;y&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn images_are_collected_and_replaced_by_their_alt_text() {
+        let markup = parse_markup(
+            "<img src=\"file:///tmp/a.png\" alt=\"first\"/> <img src=\"https://b.png\">",
+        );
+        assert_eq!(markup.text, "first ");
+        assert_eq!(markup.images, vec!["/tmp/a.png", "https://b.png"]);
+        assert_eq!(text("<img alt=\"a > b\">"), "a &gt; b");
+        assert_eq!(text("<img src=\"x\"></img>"), "");
+    }
+}
@@ -0,0 +1,269 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{Notification, Urgency};
+
+/// A [[rule]] of the config file.
+/// All conditions that are set have to match, app_name, summary and body are regular expressions.
+#[derive(Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub app_name: Option<String>,
+    pub summary: Option<String>,
+    pub body: Option<String>,
+    pub category: Option<String>,
+    pub urgency: Option<i32>,
+    pub desktop_entry: Option<String>,
+    /// Discards the notification, later rules are not evaluated.
+    #[serde(default)]
+    pub drop: bool,
+    /// Stores the notification without showing a popup.
+    #[serde(default)]
+    pub hide_popup: bool,
+    pub set_urgency: Option<i32>,
+    /// Timeout in seconds.
+    pub timeout: Option<u64>,
+    pub css_class: Option<String>,
+    /// Shows the popup even with do not disturb.
+    #[serde(default)]
+    pub ignore_dnd: bool,
+    #[serde(default)]
+    pub transient: bool,
+    /// Runs with the app name, summary and body as arguments.
+    pub command: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Rule {
+    pub config: RuleConfig,
+    app_name: Option<Regex>,
+    summary: Option<Regex>,
+    body: Option<Regex>,
+}
+
+/// What the rules decided besides changing the notification itself.
+#[derive(Default)]
+pub struct RuleOutcome {
+    pub drop: bool,
+    pub hide_popup: bool,
+    pub ignore_dnd: bool,
+    pub commands: Vec<String>,
+}
+
+impl Rule {
+    pub fn compile(config: RuleConfig) -> Result<Self, String> {
+        let regex = |pattern: &Option<String>| -> Result<Option<Regex>, String> {
+            pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|error| error.to_string())
+        };
+        if let Some(urgency) = config.set_urgency {
+            Urgency::from_i32(urgency)?;
+        }
+        Ok(Self {
+            app_name: regex(&config.app_name)?,
+            summary: regex(&config.summary)?,
+            body: regex(&config.body)?,
+            config,
+        })
+    }
+
+    pub fn matches(&self, notification: &Notification) -> bool {
+        let regex_matches = |regex: &Option<Regex>, text: &str| {
+            regex.as_ref().map_or(true, |regex| regex.is_match(text))
+        };
+        regex_matches(&self.app_name, &notification.app_name)
+            && regex_matches(&self.summary, &notification.summary)
+            && regex_matches(&self.body, &notification.body)
+            && self.config.category.as_ref().map_or(true, |category| {
+                notification.category.as_ref() == Some(category)
+            })
+            && self
+                .config
+                .urgency
+                .map_or(true, |urgency| notification.urgency.to_i32() == urgency)
+            && self
+                .config
+                .desktop_entry
+                .as_ref()
+                .map_or(true, |desktop_entry| {
+                    notification.desktop_entry.as_ref() == Some(desktop_entry)
+                })
+    }
+}
+
+/// Parses and compiles each [[rule]] of the config file on its own.
+/// Returns the valid rules along with an error for every invalid rule, which is skipped.
+pub fn compile_rules(values: Vec<toml::Value>) -> (Vec<Rule>, Vec<String>) {
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for (index, value) in values.into_iter().enumerate() {
+        let rule = value
+            .try_into::<RuleConfig>()
+            .map_err(|error| error.to_string())
+            .and_then(Rule::compile);
+        match rule {
+            Ok(rule) => rules.push(rule),
+            Err(error) => errors.push(format!("rule {}: {}", index + 1, error.trim_end())),
+        }
+    }
+    (rules, errors)
+}
+
+/// Applies all matching rules in order, each rule sees the changes of the previous ones.
+pub fn apply_rules(rules: &[Rule], notification: &mut Notification) -> RuleOutcome {
+    let mut outcome = RuleOutcome::default();
+    for rule in rules {
+        if !rule.matches(notification) {
+            continue;
+        }
+        let config = &rule.config;
+        if let Some(command) = &config.command {
+            outcome.commands.push(command.clone());
+        }
+        if config.drop {
+            outcome.drop = true;
+            break;
+        }
+        outcome.hide_popup |= config.hide_popup;
+        outcome.ignore_dnd |= config.ignore_dnd;
+        notification.transient |= config.transient;
+        if let Some(urgency) = config.set_urgency {
+            notification.urgency = Urgency::from_i32(urgency).unwrap_or(Urgency::Low);
+        }
+        if let Some(timeout) = config.timeout {
+            notification.expire_timeout = timeout.saturating_mul(1000).min(i32::MAX as u64) as i32;
+        }
+        if let Some(css_class) = &config.css_class {
+            notification.css_classes.push(css_class.clone());
+        }
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, Variant};
+
+    use super::*;
+
+    fn rules(config: &str) -> Vec<Rule> {
+        let config: toml::Value = toml::from_str(config).unwrap();
+        let values = config["rule"].as_array().unwrap().clone();
+        let (rules, errors) = compile_rules(values);
+        assert!(errors.is_empty(), "{:?}", errors);
+        rules
+    }
+
+    fn notification(app_name: &str, urgency: u8, desktop_entry: Option<&str>) -> Notification {
+        let mut hints = PropMap::new();
+        hints.insert("urgency".to_string(), Variant(Box::new(urgency)));
+        if let Some(desktop_entry) = desktop_entry {
+            hints.insert(
+                "desktop-entry".to_string(),
+                Variant(Box::new(desktop_entry.to_string())),
+            );
+        }
+        Notification::create(
+            app_name.to_string(),
+            0,
+            String::new(),
+            "summary".to_string(),
+            "body".to_string(),
+            Vec::new(),
+            hints,
+            -1,
+            false,
+        )
+    }
+
+    #[test]
+    fn rules_see_the_changes_of_earlier_rules() {
+        let rules = rules(
+            "[[rule]]\napp_name = \"^mail$\"\nset_urgency = 2\ntimeout = 5\ncss_class = \"first\"\n\
+             [[rule]]\nurgency = 2\ntimeout = 10\ncss_class = \"second\"\n",
+        );
+        let mut mail = notification("mail", 1, None);
+        let outcome = apply_rules(&rules, &mut mail);
+        assert!(!outcome.drop);
+        assert!(mail.urgency == Urgency::Urgent);
+        assert_eq!(mail.expire_timeout, 10000);
+        assert_eq!(mail.css_classes, vec!["first", "second"]);
+        // the first rule doesn't match, so neither does the second
+        let mut other = notification("mailer", 1, None);
+        apply_rules(&rules, &mut other);
+        assert!(other.urgency == Urgency::Normal);
+        assert!(other.css_classes.is_empty());
+    }
+
+    #[test]
+    fn drop_skips_later_rules() {
+        let rules = rules(
+            "[[rule]]\ncommand = \"first\"\ndrop = true\n\
+             [[rule]]\ncommand = \"second\"\nhide_popup = true\ntransient = true\n",
+        );
+        let mut notification = notification("app", 1, None);
+        let outcome = apply_rules(&rules, &mut notification);
+        assert!(outcome.drop);
+        assert!(!outcome.hide_popup);
+        assert!(!notification.transient);
+        assert_eq!(outcome.commands, vec!["first"]);
+    }
+
+    #[test]
+    fn transient_and_commands_are_collected() {
+        let rules = rules(
+            "[[rule]]\ntransient = true\ncommand = \"logger\"\n\
+             [[rule]]\ncommand = \"notify-me --loud\"\nignore_dnd = true\n",
+        );
+        let mut notification = notification("app", 1, None);
+        let outcome = apply_rules(&rules, &mut notification);
+        assert!(notification.transient);
+        assert!(outcome.ignore_dnd);
+        assert_eq!(outcome.commands, vec!["logger", "notify-me --loud"]);
+    }
+
+    #[test]
+    fn desktop_entry_and_urgency_have_to_be_equal() {
+        let rules =
+            rules("[[rule]]\ndesktop_entry = \"firefox\"\nurgency = 0\nhide_popup = true\n");
+        let matches = |notification: Notification| rules[0].matches(&notification);
+        assert!(matches(notification("app", 0, Some("firefox"))));
+        assert!(!matches(notification("app", 1, Some("firefox"))));
+        assert!(!matches(notification("app", 0, Some("firefox-esr"))));
+        assert!(!matches(notification("app", 0, None)));
+    }
+
+    #[test]
+    fn invalid_rules_are_skipped() {
+        let config: toml::Value = toml::from_str(
+            "[[rule]]\napp = \"typo\"\n[[rule]]\nsummary = \"(\"\n\
+             [[rule]]\nset_urgency = 3\n[[rule]]\nsummary = \"valid\"\n",
+        )
+        .unwrap();
+        let (rules, errors) = compile_rules(config["rule"].as_array().unwrap().clone());
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].config.summary.as_deref(), Some("valid"));
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("rule 1: "));
+    }
+}
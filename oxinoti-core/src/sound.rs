@@ -0,0 +1,306 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Config, desktop::data_dirs, spawn_command, Notification};
+
+const SOUND_EXTENSIONS: [&str; 3] = ["oga", "ogg", "wav"];
+
+/// The sound a notification asked for.
+/// Looking it up touches the file system, so it is resolved and played after the daemon state is unlocked.
+pub struct SoundRequest {
+    file: Option<String>,
+    name: Option<String>,
+    theme: String,
+    player: String,
+}
+
+impl SoundRequest {
+    /// Returns the sound of a notification with the player from the config.
+    /// None if sounds are disabled, suppressed by the notification or if there is no sound.
+    pub fn create(notification: &Notification, config: &Config) -> Option<Self> {
+        if !config.sound
+            || notification.suppress_sound
+            || notification.sound_file.is_none() && notification.sound_name.is_none()
+        {
+            return None;
+        }
+        Some(Self {
+            file: notification.sound_file.clone(),
+            name: notification.sound_name.clone(),
+            theme: config.sound_theme.clone(),
+            player: config.sound_player.clone(),
+        })
+    }
+
+    pub fn play(&self) {
+        if let Some(sound) = self.resolve(&data_dirs()) {
+            play(&self.player, &sound);
+        }
+    }
+
+    /// Resolves the sound to play, sound-file takes precedence over sound-name.
+    fn resolve(&self, dirs: &[PathBuf]) -> Option<PathBuf> {
+        if let Some(file) = &self.file {
+            let path = PathBuf::from(file.strip_prefix("file://").unwrap_or(file));
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+        self.name
+            .as_deref()
+            .and_then(|name| lookup_sound_name(name, &self.theme, dirs))
+    }
+}
+
+/// Looks up a sound name according to the freedesktop sound theme specification.
+/// Names are tried from most to least specific, "message-new-email" falls back to "message-new" and "message".
+pub fn lookup_sound_name(name: &str, theme: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    let themes = theme_chain(theme, dirs);
+    let mut candidate = name;
+    loop {
+        for theme in themes.iter() {
+            if let Some(path) = find_in_theme(candidate, theme, dirs) {
+                return Some(path);
+            }
+        }
+        if let Some(path) = find_unthemed(candidate, dirs) {
+            return Some(path);
+        }
+        match candidate.rsplit_once('-') {
+            Some((shorter, _)) => candidate = shorter,
+            None => return None,
+        }
+    }
+}
+
+/// Returns the theme followed by all themes it inherits from, always ending with freedesktop.
+fn theme_chain(theme: &str, dirs: &[PathBuf]) -> Vec<String> {
+    let mut themes: Vec<String> = Vec::new();
+    let mut pending = vec![theme.to_string()];
+    while let Some(theme) = pending.pop() {
+        if themes.contains(&theme) {
+            continue;
+        }
+        for parent in inherited_themes(&theme, dirs).into_iter().rev() {
+            pending.push(parent);
+        }
+        themes.push(theme);
+    }
+    if !themes.iter().any(|theme| theme == "freedesktop") {
+        themes.push("freedesktop".to_string());
+    }
+    themes
+}
+
+fn inherited_themes(theme: &str, dirs: &[PathBuf]) -> Vec<String> {
+    for dir in dirs {
+        let index = dir.join("sounds").join(theme).join("index.theme");
+        let contents = match fs::read_to_string(index) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+        for line in contents.lines() {
+            if let Some(parents) = line.trim().strip_prefix("Inherits=") {
+                return parents
+                    .split(',')
+                    .map(|parent| parent.trim().to_string())
+                    .filter(|parent| !parent.is_empty())
+                    .collect();
+            }
+        }
+        return Vec::new();
+    }
+    Vec::new()
+}
+
+fn find_in_theme(name: &str, theme: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    for dir in dirs {
+        let theme_dir = dir.join("sounds").join(theme);
+        for subdir in ["stereo", ""] {
+            if let Some(path) = find_with_extension(&theme_dir.join(subdir), name) {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+fn find_unthemed(name: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    dirs.iter()
+        .find_map(|dir| find_with_extension(&dir.join("sounds"), name))
+}
+
+fn find_with_extension(dir: &Path, name: &str) -> Option<PathBuf> {
+    SOUND_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file())
+}
+
+/// Runs the player with the sound file as last argument.
+pub fn play(player: &str, sound: &Path) {
+    if let Err(error) = spawn_command(player, [sound]) {
+        println!("Could not play sound with {}: {}", player, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::config::parse_config;
+
+    /// A fresh directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("oxinoti-sound-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+
+    fn notification(file: Option<&str>, name: Option<&str>) -> Notification {
+        let mut notification = Notification::create(
+            String::new(),
+            0,
+            String::new(),
+            String::new(),
+            String::new(),
+            Vec::new(),
+            dbus::arg::PropMap::new(),
+            -1,
+            false,
+        );
+        notification.sound_file = file.map(str::to_string);
+        notification.sound_name = name.map(str::to_string);
+        notification
+    }
+
+    fn sound_config(player: &str) -> Config {
+        let mut config = parse_config("");
+        config.sound = true;
+        config.sound_player = player.to_string();
+        config.sound_theme = "custom".to_string();
+        config
+    }
+
+    #[test]
+    fn names_fall_back_through_inherited_themes() {
+        let dir = test_dir("themes");
+        let sounds = dir.join("sounds");
+        fs::create_dir_all(sounds.join("custom")).unwrap();
+        fs::write(
+            sounds.join("custom/index.theme"),
+            "[Sound Theme]\nInherits=parent\n",
+        )
+        .unwrap();
+        touch(&sounds.join("parent/stereo/message-new.oga"));
+        touch(&sounds.join("freedesktop/stereo/bell.wav"));
+        touch(&sounds.join("unthemed.ogg"));
+        let dirs = vec![dir.clone()];
+        assert_eq!(
+            lookup_sound_name("message-new-email", "custom", &dirs),
+            Some(sounds.join("parent/stereo/message-new.oga"))
+        );
+        assert_eq!(
+            lookup_sound_name("bell", "custom", &dirs),
+            Some(sounds.join("freedesktop/stereo/bell.wav"))
+        );
+        assert_eq!(
+            lookup_sound_name("unthemed", "custom", &dirs),
+            Some(sounds.join("unthemed.ogg"))
+        );
+        assert_eq!(lookup_sound_name("missing", "custom", &dirs), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sound_file_takes_precedence_over_sound_name() {
+        let dir = test_dir("precedence");
+        let file = dir.join("file.wav");
+        touch(&file);
+        touch(&dir.join("sounds/custom/bell.oga"));
+        let dirs = vec![dir.clone()];
+        let config = sound_config("player");
+        let uri = format!("file://{}", file.display());
+        let request = SoundRequest::create(&notification(Some(&uri), Some("bell")), &config);
+        assert_eq!(request.unwrap().resolve(&dirs), Some(file));
+        // a missing file falls back to the name
+        let request =
+            SoundRequest::create(&notification(Some("/missing.wav"), Some("bell")), &config);
+        assert_eq!(
+            request.unwrap().resolve(&dirs),
+            Some(dir.join("sounds/custom/bell.oga"))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn no_sound_when_disabled_or_suppressed() {
+        let config = sound_config("player");
+        assert!(SoundRequest::create(&notification(None, None), &config).is_none());
+        let mut suppressed = notification(None, Some("bell"));
+        suppressed.suppress_sound = true;
+        assert!(SoundRequest::create(&suppressed, &config).is_none());
+        let mut disabled = sound_config("player");
+        disabled.sound = false;
+        assert!(SoundRequest::create(&notification(None, Some("bell")), &disabled).is_none());
+    }
+
+    #[test]
+    fn player_gets_its_arguments_and_the_sound() {
+        let dir = test_dir("player");
+        let output = dir.join("arguments");
+        let player = dir.join("player.sh");
+        fs::write(
+            &player,
+            format!(
+                "#!/bin/sh\necho \"$@\" > {}.tmp\nmv {0}.tmp {0}\n",
+                output.display()
+            ),
+        )
+        .unwrap();
+        let sound = dir.join("sound.wav");
+        touch(&sound);
+        let config = sound_config(&format!("sh {} --volume 1", player.display()));
+        SoundRequest::create(&notification(sound.to_str(), None), &config)
+            .unwrap()
+            .play();
+        let start = Instant::now();
+        while !output.exists() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            format!("--volume 1 {}\n", sound.display())
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1243 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Runs the daemon against a private dbus-daemon and drives it like a client would.
+//! dbus-daemon has to be in PATH.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use dbus::{
+    arg::{prop_cast, PropMap, RefArg, Variant},
+    blocking::{
+        stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged},
+        Connection, Proxy,
+    },
+    channel::Channel,
+    message::{MatchRule, SignalArgs},
+};
+use oxinoti_core::{
+    config::{check_config, parse_config, Config, UrgencyRetention},
+    headless::HeadlessSink,
+    history::History,
+    urgency_should_ignore_dnd, CloseReason, DaemonOptions, ImageData, Notification,
+    NotificationServer, NotificationSink, Urgency, DEFAULT_BUS_NAME,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+type ImageTuple = (i32, i32, i32, bool, i32, i32, Vec<u8>);
+type StoredNotification = (
+    String,
+    u32,
+    String,
+    String,
+    String,
+    Vec<String>,
+    i32,
+    i32,
+    String,
+    i32,
+    ImageTuple,
+    String,
+);
+
+/// A private session bus that is shut down when dropped.
+struct TestBus {
+    daemon: Child,
+    address: String,
+}
+
+impl TestBus {
+    fn start() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon is required for the integration tests");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .expect("Could not read the address of the bus");
+        Self {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+
+    fn connect(&self) -> Connection {
+        let mut channel = Channel::open_private(&self.address).expect("Could not open bus");
+        channel.register().expect("Could not register on bus");
+        Connection::from(channel)
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SinkEvent {
+    Show(u32, String),
+    Close(u32, CloseReason),
+}
+
+/// Records what the daemon hands to the presenter.
+struct RecordingSink {
+    events: Sender<SinkEvent>,
+    /// Activation tokens of simulated clicks on popups.
+    tokens: Arc<Mutex<HashMap<u32, String>>>,
+}
+
+impl NotificationSink for RecordingSink {
+    fn show(&self, notification: Notification) {
+        let _ = self
+            .events
+            .send(SinkEvent::Show(notification.id, notification.summary));
+    }
+    fn close(&self, id: u32, reason: CloseReason) {
+        let _ = self.events.send(SinkEvent::Close(id, reason));
+    }
+    fn activation_token(&self, id: u32) -> Option<String> {
+        self.tokens.lock().unwrap().remove(&id)
+    }
+}
+
+/// Collects what a headless sink writes.
+#[derive(Clone, Default)]
+struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedOutput {
+    fn lines(&self) -> Vec<serde_json::Value> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+/// The properties of one PropertiesChanged signal with their values as numbers, sorted by name.
+type ChangedProperties = Vec<(String, u64)>;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Signal {
+    Closed(u32, u32),
+    ActionInvoked(u32, String),
+    ActivationToken(u32, String),
+}
+
+struct Daemon {
+    client: Connection,
+    sink: Receiver<SinkEvent>,
+    tokens: Arc<Mutex<HashMap<u32, String>>>,
+    signals: Arc<Mutex<Vec<Signal>>>,
+    properties_changed: Arc<Mutex<Vec<ChangedProperties>>>,
+    /// Receives the result of serve once the daemon stops.
+    served: Receiver<Result<(), String>>,
+    // dropped last, so the daemon is still running while the client disconnects
+    bus: TestBus,
+}
+
+fn spawn_server(
+    bus: &TestBus,
+    sink: impl NotificationSink + 'static,
+    config: Config,
+    options: DaemonOptions,
+) -> Receiver<Result<(), String>> {
+    let connection = bus.connect();
+    let (served_sender, served) = mpsc::channel();
+    thread::spawn(move || {
+        let mut server = NotificationServer::create(sink, Arc::new(config));
+        let _ = served_sender.send(server.serve(connection, options));
+    });
+    served
+}
+
+impl Daemon {
+    fn start() -> Self {
+        Self::start_with(parse_config(""))
+    }
+
+    fn start_with(config: Config) -> Self {
+        Self::start_with_options(config, DaemonOptions::default())
+    }
+
+    fn start_with_options(config: Config, options: DaemonOptions) -> Self {
+        let (events, sink) = mpsc::channel();
+        let tokens = Arc::new(Mutex::new(HashMap::new()));
+        let recording = RecordingSink {
+            events,
+            tokens: tokens.clone(),
+        };
+        Self::start_with_sink(recording, sink, tokens, config, options)
+    }
+
+    /// Starts the daemon with a headless sink that writes to output, no sink events are recorded.
+    fn start_headless(config: Config, output: SharedOutput) -> Self {
+        let sink = HeadlessSink::create(Box::new(output), Arc::new(config.clone()));
+        let options = DaemonOptions::default();
+        Self::start_with_sink(sink, mpsc::channel().1, Arc::default(), config, options)
+    }
+
+    fn start_with_sink(
+        presenter: impl NotificationSink + 'static,
+        sink: Receiver<SinkEvent>,
+        tokens: Arc<Mutex<HashMap<u32, String>>>,
+        config: Config,
+        options: DaemonOptions,
+    ) -> Self {
+        let bus = TestBus::start();
+        let served = spawn_server(&bus, presenter, config, options);
+        let client = bus.connect();
+        let signals = Arc::new(Mutex::new(Vec::new()));
+        let recorded = signals.clone();
+        client
+            .add_match(
+                MatchRule::new_signal("org.freedesktop.Notifications", "NotificationClosed"),
+                move |(id, reason): (u32, u32), _, _| {
+                    recorded.lock().unwrap().push(Signal::Closed(id, reason));
+                    true
+                },
+            )
+            .unwrap();
+        let recorded = signals.clone();
+        client
+            .add_match(
+                MatchRule::new_signal("org.freedesktop.Notifications", "ActionInvoked"),
+                move |(id, action): (u32, String), _, _| {
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push(Signal::ActionInvoked(id, action));
+                    true
+                },
+            )
+            .unwrap();
+        let recorded = signals.clone();
+        client
+            .add_match(
+                MatchRule::new_signal("org.freedesktop.Notifications", "ActivationToken"),
+                move |(id, token): (u32, String), _, _| {
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push(Signal::ActivationToken(id, token));
+                    true
+                },
+            )
+            .unwrap();
+        let properties_changed = Arc::new(Mutex::new(Vec::new()));
+        let recorded = properties_changed.clone();
+        client
+            .add_match(
+                PropertiesPropertiesChanged::match_rule(None, None).static_clone(),
+                move |changed: PropertiesPropertiesChanged, _, _| {
+                    let mut properties: ChangedProperties = changed
+                        .changed_properties
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.0.as_u64().unwrap()))
+                        .collect();
+                    properties.sort();
+                    recorded.lock().unwrap().push(properties);
+                    true
+                },
+            )
+            .unwrap();
+        let daemon = Self {
+            client,
+            sink,
+            tokens,
+            signals,
+            properties_changed,
+            served,
+            bus,
+        };
+        daemon.wait_for_name(DEFAULT_BUS_NAME);
+        daemon
+    }
+
+    /// Starts another daemon on the same bus.
+    fn start_competitor(&self, replace: bool) -> Receiver<Result<(), String>> {
+        let options = DaemonOptions {
+            replace,
+            ..Default::default()
+        };
+        self.start_other(options)
+    }
+
+    /// Starts another daemon with the given options on the same bus.
+    fn start_other(&self, options: DaemonOptions) -> Receiver<Result<(), String>> {
+        let sink = RecordingSink {
+            events: mpsc::channel().0,
+            tokens: Arc::default(),
+        };
+        spawn_server(&self.bus, sink, parse_config(""), options)
+    }
+
+    fn wait_for_name(&self, name: &str) {
+        let bus = self
+            .client
+            .with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", TIMEOUT);
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            let (has_owner,): (bool,) = bus
+                .method_call("org.freedesktop.DBus", "NameHasOwner", (name,))
+                .unwrap();
+            if has_owner {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("The daemon did not acquire its name");
+    }
+
+    fn proxy(&self) -> Proxy<'_, &Connection> {
+        self.client.with_proxy(
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            TIMEOUT,
+        )
+    }
+
+    fn notify(&self, replaces_id: u32, summary: &str, hints: PropMap) -> u32 {
+        self.notify_with_timeout(replaces_id, summary, hints, -1)
+    }
+
+    fn notify_with_timeout(
+        &self,
+        replaces_id: u32,
+        summary: &str,
+        hints: PropMap,
+        expire_timeout: i32,
+    ) -> u32 {
+        let actions: Vec<String> = vec!["default".to_string(), "Open".to_string()];
+        let (id,): (u32,) = self
+            .proxy()
+            .method_call(
+                "org.freedesktop.Notifications",
+                "Notify",
+                (
+                    "test",
+                    replaces_id,
+                    "",
+                    summary,
+                    "body",
+                    actions,
+                    hints,
+                    expire_timeout,
+                ),
+            )
+            .unwrap();
+        id
+    }
+
+    fn call<A: dbus::arg::AppendAll>(&self, method: &str, args: A) {
+        let _: () = self
+            .proxy()
+            .method_call("org.freedesktop.Notifications", method, args)
+            .unwrap();
+    }
+
+    fn stored(&self) -> Vec<StoredNotification> {
+        let (notifications,): (Vec<StoredNotification>,) = self
+            .proxy()
+            .method_call("org.freedesktop.Notifications", "GetAllNotifications", ())
+            .unwrap();
+        notifications
+    }
+
+    /// Calls QueryHistory and returns the ids of the records and the number of all matches.
+    fn query_history(&self, filters: PropMap, offset: u32, limit: u32) -> (Vec<u32>, u32) {
+        let (records, total): (Vec<PropMap>, u32) = self
+            .proxy()
+            .method_call(
+                "org.freedesktop.Notifications",
+                "QueryHistory",
+                (filters, offset, limit),
+            )
+            .unwrap();
+        let ids = records
+            .iter()
+            .map(|record| *prop_cast::<u32>(record, "id").unwrap())
+            .collect();
+        (ids, total)
+    }
+
+    fn stored_by_id(&self, id: u32) -> StoredNotification {
+        self.stored()
+            .into_iter()
+            .find(|notification| notification.1 == id)
+            .expect("notification is not stored")
+    }
+
+    fn toggle_do_not_disturb(&self) -> bool {
+        let (status,): (bool,) = self
+            .proxy()
+            .method_call("org.freedesktop.Notifications", "DoNotDisturb", ())
+            .unwrap();
+        status
+    }
+
+    /// Processes incoming signals until the expected ones arrived and returns all signals so far.
+    fn wait_for_signals(&self, count: usize) -> Vec<Signal> {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if self.signals.lock().unwrap().len() >= count {
+                break;
+            }
+            self.client.process(Duration::from_millis(50)).unwrap();
+        }
+        self.signals.lock().unwrap().clone()
+    }
+
+    /// Processes incoming signals until the expected PropertiesChanged arrived and returns all of them.
+    fn wait_for_properties_changed(&self, count: usize) -> Vec<ChangedProperties> {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if self.properties_changed.lock().unwrap().len() >= count {
+                break;
+            }
+            self.client.process(Duration::from_millis(50)).unwrap();
+        }
+        self.properties_changed.lock().unwrap().clone()
+    }
+
+    /// Gives the daemon a moment to send anything unexpected, then returns all signals.
+    fn settled_signals(&self) -> Vec<Signal> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(200) {
+            self.client.process(Duration::from_millis(50)).unwrap();
+        }
+        self.signals.lock().unwrap().clone()
+    }
+
+    fn next_sink_event(&self) -> Option<SinkEvent> {
+        self.sink.recv_timeout(Duration::from_millis(500)).ok()
+    }
+}
+
+fn hint(value: impl RefArg + 'static) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
+}
+
+fn urgency_hint(urgency: u8) -> PropMap {
+    let mut hints = PropMap::new();
+    hints.insert("urgency".to_string(), hint(urgency));
+    hints
+}
+
+#[test]
+fn notify_allocates_ids_and_stores_notifications() {
+    let daemon = Daemon::start();
+    let first = daemon.notify(0, "first", PropMap::new());
+    let second = daemon.notify(0, "second", PropMap::new());
+    assert_eq!(first, 1);
+    assert_eq!(second, 2);
+    assert_eq!(
+        daemon.next_sink_event(),
+        Some(SinkEvent::Show(1, "first".to_string()))
+    );
+    assert_eq!(
+        daemon.next_sink_event(),
+        Some(SinkEvent::Show(2, "second".to_string()))
+    );
+    let mut summaries: Vec<String> = daemon
+        .stored()
+        .into_iter()
+        .map(|notification| notification.3)
+        .collect();
+    summaries.sort();
+    assert_eq!(summaries, vec!["first", "second"]);
+}
+
+#[test]
+fn replaces_id_is_only_reused_for_existing_notifications() {
+    let daemon = Daemon::start();
+    let id = daemon.notify(0, "original", PropMap::new());
+    assert_eq!(daemon.notify(id, "replacement", PropMap::new()), id);
+    let stored = daemon.stored();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].3, "replacement");
+    let unknown = daemon.notify(42, "unknown", PropMap::new());
+    assert_ne!(unknown, 42);
+    assert_ne!(unknown, id);
+}
+
+#[test]
+fn close_notification_emits_closed_once() {
+    let daemon = Daemon::start();
+    let id = daemon.notify(0, "close me", PropMap::new());
+    daemon.next_sink_event();
+    let _: (String,) = daemon
+        .proxy()
+        .method_call("org.freedesktop.Notifications", "CloseNotification", (id,))
+        .unwrap();
+    assert_eq!(
+        daemon.wait_for_signals(1),
+        vec![Signal::Closed(id, CloseReason::Closed.to_u32())]
+    );
+    assert_eq!(
+        daemon.next_sink_event(),
+        Some(SinkEvent::Close(id, CloseReason::Closed))
+    );
+    assert!(daemon.stored().is_empty());
+    let _: (String,) = daemon
+        .proxy()
+        .method_call("org.freedesktop.Notifications", "CloseNotification", (id,))
+        .unwrap();
+    assert_eq!(daemon.settled_signals().len(), 1);
+}
+
+#[test]
+fn expired_notifications_stay_stored() {
+    let daemon = Daemon::start();
+    let id = daemon.notify(0, "expire me", PropMap::new());
+    daemon.call("RemoveNotification", (id, CloseReason::Expired.to_u32()));
+    assert_eq!(
+        daemon.wait_for_signals(1),
+        vec![Signal::Closed(id, CloseReason::Expired.to_u32())]
+    );
+    assert_eq!(daemon.stored().len(), 1);
+}
+
+#[test]
+fn do_not_disturb_hides_popups_below_the_threshold() {
+    let mut config = parse_config("");
+    config.dnd_override = 2;
+    let daemon = Daemon::start_with(config);
+    assert!(daemon.toggle_do_not_disturb());
+    let low = daemon.notify(0, "low", urgency_hint(0));
+    let normal = daemon.notify(0, "normal", urgency_hint(1));
+    let critical = daemon.notify(0, "critical", urgency_hint(2));
+    assert_eq!(
+        daemon.next_sink_event(),
+        Some(SinkEvent::Show(critical, "critical".to_string()))
+    );
+    assert_eq!(daemon.next_sink_event(), None);
+    let mut stored: Vec<u32> = daemon
+        .stored()
+        .into_iter()
+        .map(|notification| notification.1)
+        .collect();
+    stored.sort();
+    assert_eq!(stored, vec![low, normal, critical]);
+    assert!(!daemon.toggle_do_not_disturb());
+    let shown = daemon.notify(0, "after dnd", urgency_hint(0));
+    assert_eq!(
+        daemon.next_sink_event(),
+        Some(SinkEvent::Show(shown, "after dnd".to_string()))
+    );
+}
+
+#[test]
+fn hidden_transient_notifications_are_closed() {
+    let daemon = Daemon::start();
+    assert!(daemon.toggle_do_not_disturb());
+    let mut hints = urgency_hint(0);
+    hints.insert("transient".to_string(), hint(true));
+    let id = daemon.notify(0, "transient", hints);
+    assert_eq!(
+        daemon.wait_for_signals(1),
+        vec![Signal::Closed(id, CloseReason::Undefined.to_u32())]
+    );
+    assert_eq!(
+        daemon.next_sink_event(),
+        Some(SinkEvent::Close(id, CloseReason::Undefined))
+    );
+    assert!(daemon.stored().is_empty());
+    // the id is free again
+    assert_ne!(daemon.notify(id, "new", PropMap::new()), id);
+}
+
+#[test]
+fn dnd_threshold() {
+    // without dnd everything is shown
+    assert!(urgency_should_ignore_dnd(false, 3, &Urgency::Low));
+    // 0 lets everything through
+    assert!(urgency_should_ignore_dnd(true, 0, &Urgency::Low));
+    // 1 needs at least normal urgency
+    assert!(!urgency_should_ignore_dnd(true, 1, &Urgency::Low));
+    assert!(urgency_should_ignore_dnd(true, 1, &Urgency::Normal));
+    assert!(urgency_should_ignore_dnd(true, 1, &Urgency::Urgent));
+    // 2 only lets critical notifications through
+    assert!(!urgency_should_ignore_dnd(true, 2, &Urgency::Normal));
+    assert!(urgency_should_ignore_dnd(true, 2, &Urgency::Urgent));
+    // anything else blocks everything
+    assert!(!urgency_should_ignore_dnd(true, 3, &Urgency::Urgent));
+    assert!(!urgency_should_ignore_dnd(true, -1, &Urgency::Urgent));
+}
+
+#[test]
+fn invoke_action_emits_action_invoked() {
+    let daemon = Daemon::start();
+    let id = daemon.notify(0, "action", PropMap::new());
+    daemon.call("InvokeAction", (id, "default"));
+    assert_eq!(
+        daemon.wait_for_signals(1),
+        vec![Signal::ActionInvoked(id, "default".to_string())]
+    );
+}
+
+#[test]
+fn activation_token_is_sent_before_action_invoked() {
+    let daemon = Daemon::start();
+    let id = daemon.notify(0, "action", PropMap::new());
+    // a click on the popup leaves a token with the sink before the popup invokes the action
+    daemon
+        .tokens
+        .lock()
+        .unwrap()
+        .insert(id, "token".to_string());
+    daemon.call("InvokeAction", (id, "default"));
+    daemon.call("InvokeAction", (id, "other"));
+    assert_eq!(
+        daemon.wait_for_signals(3),
+        vec![
+            Signal::ActivationToken(id, "token".to_string()),
+            Signal::ActionInvoked(id, "default".to_string()),
+            Signal::ActionInvoked(id, "other".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn clicked_popup_invokes_the_action_before_it_closes() {
+    let daemon = Daemon::start();
+    let id = daemon.notify(0, "click", PropMap::new());
+    // the popup sends both calls in order and holds the token only during InvokeAction
+    daemon
+        .tokens
+        .lock()
+        .unwrap()
+        .insert(id, "token".to_string());
+    daemon.call("InvokeAction", (id, "default"));
+    daemon.call("RemoveNotification", (id, CloseReason::Dismissed.to_u32()));
+    assert_eq!(
+        daemon.wait_for_signals(3),
+        vec![
+            Signal::ActivationToken(id, "token".to_string()),
+            Signal::ActionInvoked(id, "default".to_string()),
+            Signal::Closed(id, CloseReason::Dismissed.to_u32()),
+        ]
+    );
+}
+
+#[test]
+fn malformed_hints_are_ignored() {
+    let daemon = Daemon::start();
+    let mut hints = PropMap::new();
+    hints.insert("urgency".to_string(), hint("critical".to_string()));
+    hints.insert("progress".to_string(), hint("half".to_string()));
+    hints.insert("image-path".to_string(), hint(5u32));
+    // claims 10x10 pixels but only carries 3 bytes
+    let image: ImageTuple = (10, 10, 40, true, 8, 4, vec![0; 3]);
+    hints.insert("image-data".to_string(), hint(image));
+    let id = daemon.notify(0, "malformed", hints);
+    assert_eq!(
+        daemon.next_sink_event(),
+        Some(SinkEvent::Show(id, "malformed".to_string()))
+    );
+    let stored = daemon.stored_by_id(id);
+    assert_eq!(stored.7, Urgency::Normal.to_i32());
+    assert_eq!(stored.8, "");
+    assert_eq!(stored.9, -1);
+    let empty = ImageData::empty();
+    assert_eq!(stored.10 .0, empty.width);
+    assert!(stored.10 .6.is_empty());
+}
+
+#[test]
+fn valid_image_data_is_stored() {
+    let daemon = Daemon::start();
+    let mut hints = PropMap::new();
+    let image: ImageTuple = (1, 1, 4, true, 8, 4, vec![255; 4]);
+    hints.insert("image-data".to_string(), hint(image));
+    let id = daemon.notify(0, "image", hints);
+    let stored = daemon.stored_by_id(id);
+    assert_eq!(stored.10, (1, 1, 4, true, 8, 4, vec![255; 4]));
+}
+
+#[test]
+fn second_daemon_fails_without_replace() {
+    let daemon = Daemon::start();
+    let served = daemon.start_competitor(false);
+    let error = served
+        .recv_timeout(TIMEOUT)
+        .expect("the second daemon kept running")
+        .unwrap_err();
+    assert!(error.contains("--replace"));
+    // the first daemon keeps serving
+    daemon.notify(0, "still here", PropMap::new());
+    assert!(daemon.served.try_recv().is_err());
+}
+
+#[test]
+fn replace_shuts_down_previous_daemon() {
+    let daemon = Daemon::start();
+    let served = daemon.start_competitor(true);
+    assert_eq!(daemon.served.recv_timeout(TIMEOUT), Ok(Ok(())));
+    // the replacement owns the name and answers
+    daemon.wait_for_name(DEFAULT_BUS_NAME);
+    assert_eq!(daemon.notify(0, "replaced", PropMap::new()), 1);
+    assert!(served.try_recv().is_err());
+}
+
+#[test]
+fn daemons_with_other_bus_names_run_side_by_side() {
+    let daemon = Daemon::start();
+    let options = DaemonOptions {
+        bus_name: "org.example.Notifications".to_string(),
+        ..Default::default()
+    };
+    let served = daemon.start_other(options);
+    daemon.wait_for_name("org.example.Notifications");
+    assert_eq!(daemon.notify(0, "first daemon", PropMap::new()), 1);
+    assert!(served.try_recv().is_err());
+    assert!(daemon.served.try_recv().is_err());
+}
+
+/// Returns a history file in a fresh temporary directory.
+fn history_file(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("oxinoti-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir.join("history.jsonl")
+}
+
+fn capabilities(daemon: &Daemon) -> Vec<String> {
+    let (capabilities,): (Vec<String>,) = daemon
+        .proxy()
+        .method_call("org.freedesktop.Notifications", "GetCapabilities", ())
+        .unwrap();
+    capabilities
+}
+
+#[test]
+fn persistence_is_only_advertised_with_a_history_file() {
+    let daemon = Daemon::start();
+    assert!(capabilities(&daemon).contains(&"actions".to_string()));
+    assert!(!capabilities(&daemon).contains(&"persistence".to_string()));
+    let options = DaemonOptions {
+        history: Some(history_file("capabilities")),
+        ..Default::default()
+    };
+    let daemon = Daemon::start_with_options(parse_config(""), options);
+    assert!(capabilities(&daemon).contains(&"persistence".to_string()));
+}
+
+#[test]
+fn history_file_is_only_readable_by_the_user() {
+    let path = history_file("permissions");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, "").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    // opening rewrites the file, which also fixes the permissions of older files
+    drop(History::open(&path).unwrap());
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn failed_second_daemon_leaves_the_history_alone() {
+    let path = history_file("second-daemon");
+    let options = DaemonOptions {
+        history: Some(path.clone()),
+        ..Default::default()
+    };
+    {
+        let daemon = Daemon::start_with_options(parse_config(""), options.clone());
+        daemon.notify(0, "before", PropMap::new());
+        let served = daemon.start_other(options);
+        assert!(served.recv_timeout(TIMEOUT).unwrap().is_err());
+        // the running daemon still writes to the file that is on disk
+        daemon.notify(0, "after", PropMap::new());
+    }
+    let history = History::open(&path).unwrap();
+    let summaries: Vec<&str> = history
+        .records()
+        .map(|record| record.summary.as_str())
+        .collect();
+    assert_eq!(summaries, vec!["before", "after"]);
+}
+
+#[test]
+fn history_survives_restart() {
+    let path = history_file("restart");
+    let options = DaemonOptions {
+        history: Some(path.clone()),
+        ..Default::default()
+    };
+    {
+        let daemon = Daemon::start_with_options(parse_config(""), options.clone());
+        let closed = daemon.notify(0, "closed", PropMap::new());
+        let kept = daemon.notify(0, "kept", PropMap::new());
+        daemon.call("CloseNotification", (closed,));
+        daemon.call("InvokeAction", (kept, "default"));
+    }
+    let history = History::open(&path).unwrap();
+    let records: Vec<_> = history.records().collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].summary, "closed");
+    assert_eq!(records[0].close_reason, Some(CloseReason::Closed.to_u32()));
+    assert!(!records[0].stored);
+    assert_eq!(records[1].action.as_deref(), Some("default"));
+    assert!(records[1].stored);
+    drop(history);
+
+    let daemon = Daemon::start_with_options(parse_config(""), options);
+    let stored = daemon.stored();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].1, 2);
+    assert_eq!(stored[0].3, "kept");
+    // ids continue after the restored ones
+    assert_eq!(daemon.notify(0, "new", PropMap::new()), 3);
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn query_history_filters_and_pages() {
+    let daemon = Daemon::start();
+    let mut critical = PropMap::new();
+    critical.insert("urgency".to_string(), hint(2u8));
+    critical.insert("category".to_string(), hint("device".to_string()));
+    let first = daemon.notify(0, "Disk full", critical);
+    let second = daemon.notify(0, "hello", PropMap::new());
+    let third = daemon.notify(0, "another hello", PropMap::new());
+
+    // newest first, the ids break ties within the same second
+    assert_eq!(
+        daemon.query_history(PropMap::new(), 0, 0),
+        (vec![third, second, first], 3)
+    );
+    assert_eq!(
+        daemon.query_history(PropMap::new(), 1, 1),
+        (vec![second], 3)
+    );
+
+    let mut filters = PropMap::new();
+    filters.insert("urgency".to_string(), hint(2));
+    assert_eq!(daemon.query_history(filters, 0, 0), (vec![first], 1));
+    let mut filters = PropMap::new();
+    filters.insert("category".to_string(), hint("device".to_string()));
+    assert_eq!(daemon.query_history(filters, 0, 0), (vec![first], 1));
+    let mut filters = PropMap::new();
+    filters.insert("text".to_string(), hint("HELLO".to_string()));
+    assert_eq!(
+        daemon.query_history(filters, 0, 0),
+        (vec![third, second], 2)
+    );
+    let mut filters = PropMap::new();
+    filters.insert("until".to_string(), hint(1u64));
+    assert_eq!(daemon.query_history(filters, 0, 0), (vec![], 0));
+
+    daemon.call("MarkRead", (vec![second],));
+    daemon.call("InvokeAction", (third, "default"));
+    let mut filters = PropMap::new();
+    filters.insert("read".to_string(), hint(false));
+    assert_eq!(daemon.query_history(filters, 0, 0), (vec![first], 1));
+
+    let mut filters = PropMap::new();
+    filters.insert("unknown".to_string(), hint(true));
+    let result: Result<(Vec<PropMap>, u32), _> = daemon.proxy().method_call(
+        "org.freedesktop.Notifications",
+        "QueryHistory",
+        (filters, 0u32, 0u32),
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn notification_image_is_fetched_separately() {
+    let daemon = Daemon::start();
+    let mut hints = PropMap::new();
+    let image: ImageTuple = (1, 1, 4, true, 8, 4, vec![255; 4]);
+    hints.insert("image-data".to_string(), hint(image.clone()));
+    let with_image = daemon.notify(0, "image", hints);
+    let without_image = daemon.notify(0, "no image", PropMap::new());
+    let image_of = |id: u32| -> Result<(ImageTuple,), dbus::Error> {
+        daemon.proxy().method_call(
+            "org.freedesktop.Notifications",
+            "GetNotificationImage",
+            (id,),
+        )
+    };
+    assert_eq!(image_of(with_image).unwrap().0, image);
+    assert_eq!(
+        image_of(without_image).unwrap().0 .0,
+        ImageData::empty().width
+    );
+    assert!(image_of(42).is_err());
+}
+
+#[test]
+fn retention_evicts_least_important_closed_notifications() {
+    let mut config = parse_config("");
+    config.retention.max_count = 2;
+    let path = history_file("retention-count");
+    let options = DaemonOptions {
+        history: Some(path.clone()),
+        ..Default::default()
+    };
+    let daemon = Daemon::start_with_options(config, options);
+    let critical = daemon.notify(0, "critical", urgency_hint(2));
+    let old = daemon.notify(0, "old", urgency_hint(1));
+    let new = daemon.notify(0, "new", urgency_hint(1));
+    // open notifications are never evicted
+    assert_eq!(daemon.stored().len(), 3);
+    let expired = CloseReason::Expired.to_u32();
+    daemon.call("RemoveNotification", (critical, expired));
+    daemon.call("RemoveNotification", (old, expired));
+    let newest = daemon.notify(0, "newest", urgency_hint(0));
+    assert_eq!(
+        daemon.wait_for_signals(2),
+        vec![
+            Signal::Closed(critical, expired),
+            Signal::Closed(old, expired)
+        ]
+    );
+    let mut ids: Vec<u32> = daemon.stored().iter().map(|stored| stored.1).collect();
+    ids.sort();
+    assert_eq!(ids, vec![new, newest]);
+    // the history file keeps the records of evicted notifications
+    assert_eq!(daemon.query_history(PropMap::new(), 0, 0).1, 4);
+    drop(daemon);
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn history_retention_removes_records_of_closed_notifications() {
+    let mut config = parse_config("");
+    config.retention.history_max_count = 1;
+    let path = history_file("history-count");
+    let options = DaemonOptions {
+        history: Some(path.clone()),
+        ..Default::default()
+    };
+    let daemon = Daemon::start_with_options(config, options);
+    let dismissed = daemon.notify(0, "dismissed", PropMap::new());
+    let open = daemon.notify(0, "open", PropMap::new());
+    // records of stored notifications are always kept
+    assert_eq!(daemon.query_history(PropMap::new(), 0, 0).1, 2);
+    daemon.call("CloseNotification", (dismissed,));
+    let new = daemon.notify(0, "new", PropMap::new());
+    assert_eq!(
+        daemon.query_history(PropMap::new(), 0, 0),
+        (vec![new, open], 2)
+    );
+    drop(daemon);
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn history_without_a_file_only_keeps_stored_notifications() {
+    let daemon = Daemon::start();
+    let dismissed = daemon.notify(0, "dismissed", PropMap::new());
+    let open = daemon.notify(0, "open", PropMap::new());
+    daemon.call("CloseNotification", (dismissed,));
+    let new = daemon.notify(0, "new", PropMap::new());
+    assert_eq!(
+        daemon.query_history(PropMap::new(), 0, 0),
+        (vec![new, open], 2)
+    );
+}
+
+#[test]
+fn retention_drops_images_over_the_limit() {
+    let mut config = parse_config("");
+    config.retention.max_image_bytes = 4;
+    let daemon = Daemon::start_with(config);
+    let image: ImageTuple = (1, 1, 4, true, 8, 4, vec![255; 4]);
+    let mut hints = PropMap::new();
+    hints.insert("image-data".to_string(), hint(image.clone()));
+    let first = daemon.notify(0, "first", hints);
+    let mut hints = PropMap::new();
+    hints.insert("image-data".to_string(), hint(image.clone()));
+    let second = daemon.notify(0, "second", hints);
+    assert_eq!(daemon.stored_by_id(first).10 .0, ImageData::empty().width);
+    assert_eq!(daemon.stored_by_id(second).10, image);
+}
+
+#[test]
+fn retention_removes_old_notifications_with_urgency_overrides() {
+    let path = history_file("retention");
+    {
+        let mut history = History::open(&path).unwrap();
+        for (id, urgency) in [(1, 1), (2, 2)] {
+            let mut hints = PropMap::new();
+            hints.insert("urgency".to_string(), hint(urgency as u8));
+            let mut notification = Notification::create(
+                "test".to_string(),
+                0,
+                String::new(),
+                "old".to_string(),
+                String::new(),
+                Vec::new(),
+                hints,
+                -1,
+                false,
+            );
+            notification.id = id;
+            notification.received = 0;
+            history.record(&notification);
+        }
+    }
+    let mut config = parse_config("");
+    config.retention.max_age = 60;
+    config.retention.critical = Some(UrgencyRetention { max_age: 0 });
+    config.retention.history_max_age = 0;
+    let options = DaemonOptions {
+        history: Some(path.clone()),
+        ..Default::default()
+    };
+    let daemon = Daemon::start_with_options(config.clone(), options.clone());
+    assert_eq!(daemon.stored().len(), 1);
+    assert_eq!(daemon.query_history(PropMap::new(), 0, 0), (vec![2, 1], 2));
+    drop(daemon);
+    let history = History::open(&path).unwrap();
+    assert!(!history.get(1).unwrap().stored);
+    drop(history);
+    // old records are only removed by the history retention
+    config.retention.history_max_age = 60;
+    let daemon = Daemon::start_with_options(config, options);
+    assert_eq!(daemon.query_history(PropMap::new(), 0, 0), (vec![2], 1));
+    drop(daemon);
+    assert_eq!(History::open(&path).unwrap().len(), 1);
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+}
+
+/// Writes the config to a temporary file and returns its path.
+fn config_file(name: &str, contents: &str) -> String {
+    let path = env::temp_dir().join(format!("oxinoti-test-{}-{}.toml", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    path.to_string_lossy().into()
+}
+
+#[test]
+fn rules_drop_hide_and_change_notifications() {
+    let path = config_file(
+        "rules",
+        r#"
+        [[rule]]
+        summary = "^spam"
+        drop = true
+
+        [[rule]]
+        app_name = "^quiet$"
+        hide_popup = true
+        set_urgency = 2
+        timeout = 10
+        css_class = "quiet"
+
+        [[rule]]
+        category = "im.received"
+        ignore_dnd = true
+        "#,
+    );
+    let daemon = Daemon::start_with(parse_config(&path));
+    let _ = fs::remove_file(&path);
+
+    let dropped = daemon.notify(0, "spam offer", PropMap::new());
+    assert_eq!(
+        daemon.wait_for_signals(1),
+        vec![Signal::Closed(dropped, CloseReason::Undefined.to_u32())]
+    );
+    assert!(daemon.stored().is_empty());
+    assert_eq!(daemon.next_sink_event(), None);
+
+    let actions: Vec<String> = Vec::new();
+    let (quiet,): (u32,) = daemon
+        .proxy()
+        .method_call(
+            "org.freedesktop.Notifications",
+            "Notify",
+            (
+                "quiet",
+                0u32,
+                "",
+                "hidden",
+                "body",
+                actions,
+                PropMap::new(),
+                -1,
+            ),
+        )
+        .unwrap();
+    assert_eq!(daemon.next_sink_event(), None);
+    let stored = daemon.stored_by_id(quiet);
+    assert_eq!(stored.6, 10000);
+    assert_eq!(stored.7, 2);
+
+    assert!(daemon.toggle_do_not_disturb());
+    let mut hints = urgency_hint(0);
+    hints.insert("category".to_string(), hint("im.received".to_string()));
+    let message = daemon.notify(0, "message", hints);
+    assert_eq!(
+        daemon.next_sink_event(),
+        Some(SinkEvent::Show(message, "message".to_string()))
+    );
+}
+
+#[test]
+fn invalid_rules_are_reported() {
+    let path = config_file("invalid-regex", "[[rule]]\nsummary = \"(\"\ndrop = true\n");
+    assert!(check_config(&path).is_err());
+    // an invalid rule must not drop everything
+    assert!(parse_config(&path).rules.is_empty());
+    let _ = fs::remove_file(&path);
+    let path = config_file(
+        "unknown-field",
+        "timeout = 7\n[[rule]]\napp = \"typo\"\ndrop = true\n[[rule]]\nsummary = \"valid\"\nhide_popup = true\n",
+    );
+    assert!(check_config(&path).is_err());
+    // only the rule with the typo is skipped, the rest of the config is kept
+    let config = parse_config(&path);
+    assert_eq!(config.timeout, 7);
+    assert_eq!(config.rules.len(), 1);
+    assert_eq!(config.rules[0].config.summary.as_deref(), Some("valid"));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn only_allowed_link_schemes_are_opened() {
+    let config = parse_config("");
+    assert_eq!(
+        config.allowed_link_scheme("https://example.com"),
+        Ok("https")
+    );
+    assert_eq!(
+        config.allowed_link_scheme("MAILTO:me@example.com"),
+        Ok("MAILTO")
+    );
+    assert!(config.allowed_link_scheme("file:///etc/passwd").is_err());
+    assert!(config.allowed_link_scheme("steam://run/1").is_err());
+    assert!(config.allowed_link_scheme("no scheme").is_err());
+    let path = config_file("link-schemes", "link_schemes = [\"steam\"]\n");
+    let config = parse_config(&path);
+    assert!(config.allowed_link_scheme("steam://run/1").is_ok());
+    assert!(config.allowed_link_scheme("https://example.com").is_err());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn headless_sink_writes_events_and_expires_notifications() {
+    let output = SharedOutput::default();
+    let daemon = Daemon::start_headless(parse_config(""), output.clone());
+    let id = daemon.notify_with_timeout(0, "headless", PropMap::new(), 100);
+    // the timeout is simulated without a session bus
+    assert_eq!(
+        daemon.wait_for_signals(1),
+        vec![Signal::Closed(id, CloseReason::Expired.to_u32())]
+    );
+    let lines = output.lines();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["event"], "show");
+    assert_eq!(lines[0]["id"], id);
+    assert_eq!(lines[0]["summary"], "headless");
+    assert_eq!(lines[0]["expire_timeout"], 100);
+    assert_eq!(lines[0]["actions"][0]["key"], "default");
+    assert_eq!(
+        lines[1],
+        serde_json::json!({"event": "close", "id": id, "reason": 1})
+    );
+    // expired notifications stay stored
+    assert_eq!(daemon.stored().len(), 1);
+}
+
+#[test]
+fn properties_are_readable_writable_and_report_changes() {
+    let daemon = Daemon::start();
+    let interface = "org.freedesktop.Notifications";
+    let get = |name: &str| -> bool { daemon.proxy().get(interface, name).unwrap() };
+    let count = || -> u32 { daemon.proxy().get(interface, "Count").unwrap() };
+    assert!(!get("DoNotDisturb"));
+    assert!(!get("NotificationCenterOpen"));
+    assert!(!get("Paused"));
+    assert_eq!(count(), 0);
+    let changed = |name: &str, value: u64| vec![(name.to_string(), value)];
+
+    let id = daemon.notify(0, "count", PropMap::new());
+    assert_eq!(
+        daemon.wait_for_properties_changed(1)[0],
+        changed("Count", 1)
+    );
+    assert_eq!(count(), 1);
+    daemon.call("CloseNotification", (id,));
+    assert_eq!(
+        daemon.wait_for_properties_changed(2)[1],
+        changed("Count", 0)
+    );
+    daemon.call("SetPaused", (true,));
+    assert_eq!(
+        daemon.wait_for_properties_changed(3)[2],
+        changed("Paused", 1)
+    );
+    assert!(get("Paused"));
+
+    // writing through org.freedesktop.DBus.Properties
+    daemon.proxy().set(interface, "DoNotDisturb", true).unwrap();
+    assert_eq!(
+        daemon.wait_for_properties_changed(4)[3],
+        changed("DoNotDisturb", 1)
+    );
+    daemon
+        .proxy()
+        .set(interface, "NotificationCenterOpen", true)
+        .unwrap();
+    assert_eq!(
+        daemon.wait_for_properties_changed(5)[4],
+        changed("NotificationCenterOpen", 1)
+    );
+    daemon.proxy().set(interface, "Paused", false).unwrap();
+    assert_eq!(
+        daemon.wait_for_properties_changed(6)[5],
+        changed("Paused", 0)
+    );
+    assert!(get("DoNotDisturb"));
+    assert!(get("NotificationCenterOpen"));
+    assert!(!get("Paused"));
+    // writing the current value again changes nothing and Count is read-only
+    daemon.proxy().set(interface, "DoNotDisturb", true).unwrap();
+    assert!(daemon.proxy().set(interface, "Count", 5u32).is_err());
+    daemon.settled_signals();
+    assert_eq!(daemon.properties_changed.lock().unwrap().len(), 6);
+}
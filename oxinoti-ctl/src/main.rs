@@ -0,0 +1,352 @@
+/*
+Copyright © 2023 Fabio Lenherr
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{process, time::Duration};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use dbus::{
+    arg::{prop_cast, PropMap, RefArg, Variant},
+    blocking::{stdintf::org_freedesktop_dbus::Properties, Connection, Proxy},
+};
+use oxinoti_core::{parse_bus_name, DEFAULT_BUS_NAME};
+use serde::Serialize;
+
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+#[derive(Parser)]
+#[command(version, about = "Controls the OxiNoti notification daemon.")]
+struct Cli {
+    /// Bus name of the daemon, if it was started with --bus-name.
+    #[arg(
+        long,
+        global = true,
+        default_value = DEFAULT_BUS_NAME,
+        value_parser = parse_bus_name
+    )]
+    bus_name: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Changes or shows do not disturb.
+    Dnd {
+        #[arg(value_enum)]
+        state: DndState,
+    },
+    /// Lists all stored notifications.
+    List {
+        /// Print the notifications as a JSON array.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Closes a notification.
+    Close { id: u32 },
+    /// Removes all notifications.
+    Clear,
+    /// Invokes an action of a notification.
+    Invoke { id: u32, action: String },
+    /// Sends an inline reply to a notification.
+    Reply { id: u32, text: String },
+    /// Opens or closes the notification center.
+    Center {
+        #[arg(value_enum)]
+        state: CenterState,
+    },
+    /// Reloads the config file of the daemon.
+    Reload,
+    /// Lists the notification history, newest first.
+    History {
+        /// Only notifications of this app.
+        #[arg(long)]
+        app: Option<String>,
+        /// Only notifications with this urgency, 0 for low, 1 for normal and 2 for critical.
+        #[arg(long, value_parser = clap::value_parser!(i32).range(0..=2))]
+        urgency: Option<i32>,
+        /// Only notifications with this category.
+        #[arg(long)]
+        category: Option<String>,
+        /// Only notifications received at or after this unix time.
+        #[arg(long)]
+        since: Option<u64>,
+        /// Only notifications received before this unix time.
+        #[arg(long)]
+        until: Option<u64>,
+        /// Only notifications that contain this text in the summary or body.
+        #[arg(long)]
+        text: Option<String>,
+        /// Only notifications that were not read yet.
+        #[arg(long)]
+        unread: bool,
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+        /// Maximum number of notifications, 0 lists all of them.
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+        /// Print the notifications as a JSON array.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone)]
+enum DndState {
+    On,
+    Off,
+    Toggle,
+    Status,
+}
+
+#[derive(ValueEnum, Clone)]
+enum CenterState {
+    Open,
+    Close,
+}
+
+type ImageData = (i32, i32, i32, bool, i32, i32, Vec<u8>);
+type StoredNotification = (
+    String,
+    u32,
+    String,
+    String,
+    String,
+    Vec<String>,
+    i32,
+    i32,
+    String,
+    i32,
+    ImageData,
+    String,
+);
+
+/// A stored notification without its image data.
+#[derive(Serialize)]
+struct ListedNotification {
+    id: u32,
+    app_name: String,
+    app_icon: String,
+    summary: String,
+    body: String,
+    actions: Vec<String>,
+    expire_timeout: i32,
+    urgency: i32,
+    image_path: String,
+    progress: i32,
+    category: String,
+}
+
+/// A record of the notification history.
+#[derive(Serialize)]
+struct HistoryEntry {
+    id: u32,
+    received: u64,
+    app_name: String,
+    summary: String,
+    body: String,
+    urgency: i32,
+    category: String,
+    close_reason: u32,
+    action: String,
+    read: bool,
+}
+
+impl HistoryEntry {
+    fn from_props(props: &PropMap) -> Self {
+        let text = |name: &str| {
+            prop_cast::<String>(props, name)
+                .cloned()
+                .unwrap_or_default()
+        };
+        Self {
+            id: prop_cast(props, "id").copied().unwrap_or_default(),
+            received: prop_cast(props, "received").copied().unwrap_or_default(),
+            app_name: text("app_name"),
+            summary: text("summary"),
+            body: text("body"),
+            urgency: prop_cast(props, "urgency").copied().unwrap_or_default(),
+            category: text("category"),
+            close_reason: prop_cast(props, "close_reason")
+                .copied()
+                .unwrap_or_default(),
+            action: text("action"),
+            read: prop_cast(props, "read").copied().unwrap_or_default(),
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let conn = match Connection::new_session() {
+        Ok(conn) => conn,
+        Err(error) => {
+            eprintln!("Could not connect to the session bus: {}", error);
+            process::exit(1);
+        }
+    };
+    let proxy = conn.with_proxy(
+        cli.bus_name.as_str(),
+        "/org/freedesktop/Notifications",
+        Duration::from_millis(1000),
+    );
+    if let Err(error) = run(cli.command, &proxy) {
+        eprintln!("Could not reach OxiNoti: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(command: Command, proxy: &Proxy<&Connection>) -> Result<(), dbus::Error> {
+    match command {
+        Command::Dnd { state } => {
+            let status = match state {
+                DndState::On | DndState::Off => {
+                    let status = matches!(state, DndState::On);
+                    let _: () = proxy.method_call(INTERFACE, "SetDoNotDisturb", (status,))?;
+                    status
+                }
+                DndState::Toggle => {
+                    let (status,): (bool,) = proxy.method_call(INTERFACE, "DoNotDisturb", ())?;
+                    status
+                }
+                DndState::Status => proxy.get(INTERFACE, "DoNotDisturb")?,
+            };
+            println!("{}", if status { "on" } else { "off" });
+        }
+        Command::List { json } => list(proxy, json)?,
+        Command::Close { id } => {
+            let _: (String,) = proxy.method_call(INTERFACE, "CloseNotification", (id,))?;
+        }
+        Command::Clear => {
+            let _: (String,) = proxy.method_call(INTERFACE, "RemoveAllNotifications", ())?;
+        }
+        Command::Invoke { id, action } => {
+            let _: () = proxy.method_call(INTERFACE, "InvokeAction", (id, action))?;
+        }
+        Command::Reply { id, text } => {
+            let _: () = proxy.method_call(INTERFACE, "InlineReply", (id, text))?;
+        }
+        Command::Center { state } => {
+            let open = matches!(state, CenterState::Open);
+            let _: () = proxy.method_call(INTERFACE, "SetNotificationCenterOpen", (open,))?;
+        }
+        Command::Reload => {
+            let _: () = proxy.method_call(INTERFACE, "ReloadConfig", ())?;
+        }
+        Command::History {
+            app,
+            urgency,
+            category,
+            since,
+            until,
+            text,
+            unread,
+            offset,
+            limit,
+            json,
+        } => {
+            let mut filters = PropMap::new();
+            let mut add = |name: &str, value: Box<dyn RefArg>| {
+                filters.insert(name.to_string(), Variant(value));
+            };
+            if let Some(app) = app {
+                add("app_name", Box::new(app));
+            }
+            if let Some(urgency) = urgency {
+                add("urgency", Box::new(urgency));
+            }
+            if let Some(category) = category {
+                add("category", Box::new(category));
+            }
+            if let Some(since) = since {
+                add("since", Box::new(since));
+            }
+            if let Some(until) = until {
+                add("until", Box::new(until));
+            }
+            if let Some(text) = text {
+                add("text", Box::new(text));
+            }
+            if unread {
+                add("read", Box::new(false));
+            }
+            history(proxy, filters, offset, limit, json)?;
+        }
+    }
+    Ok(())
+}
+
+fn history(
+    proxy: &Proxy<&Connection>,
+    filters: PropMap,
+    offset: u32,
+    limit: u32,
+    json: bool,
+) -> Result<(), dbus::Error> {
+    let (records, _total): (Vec<PropMap>, u32) =
+        proxy.method_call(INTERFACE, "QueryHistory", (filters, offset, limit))?;
+    let entries: Vec<HistoryEntry> = records.iter().map(HistoryEntry::from_props).collect();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&entries).expect("Failed to serialize notifications.")
+        );
+        return Ok(());
+    }
+    for entry in entries {
+        println!(
+            "{}\t{}\t{}\t{}",
+            entry.id, entry.received, entry.app_name, entry.summary
+        );
+    }
+    Ok(())
+}
+
+fn list(proxy: &Proxy<&Connection>, json: bool) -> Result<(), dbus::Error> {
+    let (stored,): (Vec<StoredNotification>,) =
+        proxy.method_call(INTERFACE, "GetAllNotifications", ())?;
+    let mut notifications: Vec<ListedNotification> = stored
+        .into_iter()
+        .map(|notification| ListedNotification {
+            id: notification.1,
+            app_name: notification.0,
+            app_icon: notification.2,
+            summary: notification.3,
+            body: notification.4,
+            actions: notification.5,
+            expire_timeout: notification.6,
+            urgency: notification.7,
+            image_path: notification.8,
+            progress: notification.9,
+            category: notification.11,
+        })
+        .collect();
+    notifications.sort_by_key(|notification| notification.id);
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&notifications).expect("Failed to serialize notifications.")
+        );
+        return Ok(());
+    }
+    for notification in notifications {
+        println!(
+            "{}\t{}\t{}",
+            notification.id, notification.app_name, notification.summary
+        );
+    }
+    Ok(())
+}
@@ -17,68 +17,128 @@ along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 #![feature(cell_update)]
 #![feature(string_remove_matches)]
-use std::{env, fs, path::PathBuf};
+use std::{fs, io, path::PathBuf, process, sync::Arc};
 
+use clap::Parser;
 use directories_next as dirs;
+use oxinoti_core::{
+    config::{check_config, parse_config},
+    headless::HeadlessSink,
+    parse_bus_name, DaemonOptions, NotificationServer, DEFAULT_BUS_NAME,
+};
 use ui::initialize_ui;
 
-mod daemon;
 pub mod ui;
 
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Use a specific path to load a css style sheet.
+    #[arg(long)]
+    css: Option<PathBuf>,
+    /// Use a specific path to load a config file.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Print notifications as JSON lines instead of showing popups.
+    #[arg(long)]
+    headless: bool,
+    /// Print every notification that is received or closed.
+    #[arg(long, short)]
+    verbose: bool,
+    /// Replace a notification daemon that is already running.
+    #[arg(long)]
+    replace: bool,
+    /// Own this bus name instead of org.freedesktop.Notifications.
+    #[arg(long, default_value = DEFAULT_BUS_NAME, value_parser = parse_bus_name)]
+    bus_name: String,
+    /// Check the config file and exit.
+    #[arg(long)]
+    check_config: bool,
+    /// Do not keep a notification history on disk.
+    #[arg(long)]
+    no_history: bool,
+}
+
 fn main() {
-    let mut config_strings: (String, String) = ("".to_string(), "".to_string());
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        let mut argiter = args.iter();
-        argiter.next().unwrap();
-        loop {
-            let maybe_next = argiter.next();
-            if maybe_next.is_none() {
-                break;
-            }
-            match maybe_next.unwrap().as_str() {
-                "--css" => {
-                    let next = argiter.next();
-                    if next.is_some() {
-                        config_strings.0 = next.unwrap().clone();
-                    }
-                }
-                "--config" => {
-                    let next = argiter.next();
-                    if next.is_some() {
-                        config_strings.1 = next.unwrap().clone();
-                    }
-                }
-                _ => {
-                    print!(
-                        "usage:
-    --css: use a specific path to load a css style sheet.
-    --config: use a specific path to load a config file.
-    --help: show this message.\n"
-                    );
-                    return;
-                }
+    let args = Args::parse();
+    let (css_file, config_file) = match create_config_dir(args.css, args.config) {
+        Ok(paths) => paths,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    if args.check_config {
+        match check_config(&config_file) {
+            Ok(()) => println!("{} is valid", config_file),
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
             }
         }
+        return;
     }
-    config_strings = create_config_dir(config_strings.0, config_strings.1);
+    let options = DaemonOptions {
+        replace: args.replace,
+        verbose: args.verbose,
+        history: if args.no_history {
+            None
+        } else {
+            history_file()
+        },
+        bus_name: args.bus_name,
+        name: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
 
-    initialize_ui(config_strings.0, config_strings.1);
+    if args.headless {
+        run_headless(&config_file, options);
+    } else {
+        initialize_ui(css_file, config_file, options);
+    }
+}
+
+/// Runs the daemon without a display, notifications are written to stdout.
+fn run_headless(config_file: &str, options: DaemonOptions) {
+    let config = Arc::new(parse_config(config_file));
+    let sink = HeadlessSink::create(Box::new(io::stdout()), config.clone());
+    if let Err(error) = NotificationServer::create(sink, config).run(options) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
 }
 
-fn create_config_dir(css_string: String, toml_string: String) -> (String, String) {
+/// Returns the file of the notification history in the data directory.
+fn history_file() -> Option<PathBuf> {
+    let dirs = dirs::ProjectDirs::from("com", "dashie", "oxinoti");
+    if dirs.is_none() {
+        println!("Could not get data directory, no history is kept");
+    }
+    dirs.map(|dirs| dirs.data_dir().join("history.jsonl"))
+}
+
+/// Returns the css and config paths, paths given on the command line have to exist.
+/// Without them the files in the config directory are used and created if necessary.
+fn create_config_dir(
+    css_path: Option<PathBuf>,
+    config_path: Option<PathBuf>,
+) -> Result<(String, String), String> {
+    for path in [&css_path, &config_path].into_iter().flatten() {
+        if !path.exists() {
+            return Err(format!("{} does not exist", path.display()));
+        }
+    }
     let maybe_config_dir = dirs::ProjectDirs::from("com", "dashie", "oxinoti");
     if maybe_config_dir.is_none() {
         panic!("Could not get config directory");
     }
     let config = maybe_config_dir.unwrap();
     let config_dir = config.config_dir();
-    let mut file_path: PathBuf = PathBuf::from(css_string);
-    if !file_path.exists() {
+    let file_path = css_path.unwrap_or_else(|| {
         if !config_dir.exists() {
-            fs::create_dir(config_dir).expect("Could not create config directory");
+            fs::create_dir_all(config_dir).expect("Could not create config directory");
         }
-        file_path = config_dir.join("style.css");
+        let file_path = config_dir.join("style.css");
         if !file_path.exists() {
             fs::File::create(&file_path).expect("Could not create css config file");
             fs::write(
@@ -89,21 +149,22 @@ fn create_config_dir(css_string: String, toml_string: String) -> (String, String
             )
             .expect("Could not write default values");
         }
-    }
-    let mut config_path: PathBuf = PathBuf::from(toml_string);
-    if !config_path.exists() {
+        file_path
+    });
+    let config_path = config_path.unwrap_or_else(|| {
         if !config_dir.exists() {
-            fs::create_dir(config_dir).expect("Could not create config directory");
+            fs::create_dir_all(config_dir).expect("Could not create config directory");
         }
-        config_path = config_dir.join("oxinoti.toml");
+        let config_path = config_dir.join("oxinoti.toml");
         if !config_path.exists() {
             fs::File::create(&config_path).expect("Could not create config file");
             fs::write(&config_path, "timeout = 3\ndnd_override = 2")
                 .expect("Could not write default values");
         }
-    }
-    (
-        file_path.to_str().unwrap().into(),
-        config_path.to_str().unwrap().into(),
-    )
+        config_path
+    });
+    Ok((
+        file_path.to_string_lossy().into(),
+        config_path.to_string_lossy().into(),
+    ))
 }
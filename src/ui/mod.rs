@@ -22,7 +22,8 @@ use std::{
     cell::Cell,
     collections::HashMap,
     path::Path,
-    sync::{Arc, Mutex, RwLock},
+    process,
+    sync::{mpsc, Arc, Mutex, OnceLock, RwLock},
     thread,
     time::Duration,
 };
@@ -30,36 +31,115 @@ use std::{
 use gtk::gdk_pixbuf::Pixbuf;
 use gtk::{
     gdk,
-    gio::SimpleAction,
+    gio::{self, prelude::AppLaunchContextExt, AppInfoCreateFlags, ApplicationFlags, SimpleAction},
     glib::{self, clone, Sender},
     pango,
-    prelude::{ApplicationExt, ApplicationExtManual},
+    prelude::{ApplicationExt, ApplicationExtManual, Cast},
     subclass::prelude::ObjectSubclassIsExt,
     traits::{
-        BoxExt, ButtonExt, ContainerExt, CssProviderExt, EntryExt, GtkWindowExt, ImageExt,
-        LabelExt, ProgressBarExt, StyleContextExt, WidgetExt,
+        BoxExt, ButtonExt, ContainerExt, CssProviderExt, EntryExt, GtkWindowExt, IconThemeExt,
+        ImageExt, LabelExt, ProgressBarExt, StyleContextExt, WidgetExt,
     },
-    Align, Application, Box, Button, Image, Inhibit, Label, PackType, ProgressBar, StyleContext,
-    Window, WindowType,
+    Align, Application, Box, Button, IconSize, IconTheme, Image, Inhibit, Label, PackType,
+    ProgressBar, StyleContext, Window, WindowType,
 };
 use gtk_layer_shell::Edge;
 
-use crate::{
-    daemon::{ImageData, Notification, NotificationServer},
-    ui::utils::config::parse_config,
+use oxinoti_core::{
+    config::{parse_config, Config},
+    markup::parse_markup,
+    spawn_command, timeout_millis, CloseReason, DaemonOptions, ImageData, Notification,
+    NotificationServer, NotificationSink,
 };
 
-use self::utils::{config::Config, NotificationBox};
+use self::utils::NotificationBox;
 
 const APP_ID: &str = "org.dashie.oxinoti";
 
+/// Activation tokens of clicks on popups, held only while the InvokeAction of the click is handled.
+type ActivationTokens = Arc<Mutex<HashMap<u32, String>>>;
+
+/// Bus name of the daemon the popups belong to, the ui runs exactly one daemon per process.
+static BUS_NAME: OnceLock<String> = OnceLock::new();
+
+fn bus_name() -> &'static str {
+    BUS_NAME.get().expect("The ui was not initialized.")
+}
+
+/// Method calls of the popups to the daemon.
+enum DaemonCall {
+    /// Carries the activation token of the click, if there is one.
+    InvokeAction(u32, String, Option<String>),
+    InlineReply(u32, String),
+    RemoveNotification(u32, CloseReason),
+}
+
+/// Queue of the calls to the daemon, they are sent in order on one connection,
+/// so e.g. ActionInvoked is always emitted before the NotificationClosed of the same click.
+static DAEMON_CALLS: OnceLock<mpsc::Sender<DaemonCall>> = OnceLock::new();
+
+fn start_daemon_calls(tokens: ActivationTokens) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || send_daemon_calls(rx, tokens));
+    DAEMON_CALLS
+        .set(tx)
+        .expect("The daemon calls can only be started once.");
+}
+
+fn call_daemon(call: DaemonCall) {
+    let calls = DAEMON_CALLS.get().expect("The ui was not initialized.");
+    let _ = calls.send(call);
+}
+
+/// Sends the queued calls, the token of an action is only handed to the sink while its InvokeAction is handled.
+fn send_daemon_calls(calls: mpsc::Receiver<DaemonCall>, tokens: ActivationTokens) {
+    use dbus::blocking::Connection;
+
+    let conn = match Connection::new_session() {
+        Ok(conn) => conn,
+        Err(error) => {
+            println!("Could not connect to the daemon: {}", error);
+            return;
+        }
+    };
+    let proxy = conn.with_proxy(
+        bus_name(),
+        "/org/freedesktop/Notifications",
+        Duration::from_millis(1000),
+    );
+    for call in calls {
+        let _: Result<(), dbus::Error> = match call {
+            DaemonCall::InvokeAction(id, action, token) => {
+                if let Some(token) = token {
+                    tokens.lock().unwrap().insert(id, token);
+                }
+                let result = proxy.method_call(
+                    "org.freedesktop.Notifications",
+                    "InvokeAction",
+                    (id, action),
+                );
+                tokens.lock().unwrap().remove(&id);
+                result
+            }
+            DaemonCall::InlineReply(id, text) => {
+                proxy.method_call("org.freedesktop.Notifications", "InlineReply", (id, text))
+            }
+            DaemonCall::RemoveNotification(id, reason) => proxy.method_call(
+                "org.freedesktop.Notifications",
+                "RemoveNotification",
+                (id, reason.to_u32()),
+            ),
+        };
+    }
+}
+
 pub fn remove_notification(
     mainbox: &Box,
     window: &Window,
     noticount: Arc<Cell<i32>>,
     id: u32,
     id_map: Arc<RwLock<HashMap<u32, Arc<NotificationBox>>>>,
-    timed_out: bool,
+    reason: CloseReason,
     mutex: Arc<Mutex<bool>>,
 ) {
     let _guard = mutex.lock().unwrap();
@@ -68,6 +148,7 @@ pub fn remove_notification(
         return;
     }
     let notibox = notiopt.unwrap();
+    *notibox.imp().removed.lock().unwrap() = true;
 
     notibox.unmap();
 
@@ -81,21 +162,7 @@ pub fn remove_notification(
         window.hide();
     }
 
-    if timed_out {
-        return;
-    }
-    thread::spawn(move || {
-        use dbus::blocking::Connection;
-
-        let conn = Connection::new_session().unwrap();
-        let proxy = conn.with_proxy(
-            "org.freedesktop.Notifications",
-            "/org/freedesktop/Notifications",
-            Duration::from_millis(1000),
-        );
-        let _: Result<(), dbus::Error> =
-            proxy.method_call("org.freedesktop.Notifications", "CloseNotification", (id,));
-    });
+    call_daemon(DaemonCall::RemoveNotification(id, reason));
 }
 
 pub fn show_notification(
@@ -116,11 +183,11 @@ pub fn show_notification(
     let notibutton = Button::new();
     notibox.set_opacity(1.0);
     notibox.style_context().add_class("NotificationBox");
-    notibox.imp().notification_id.set(notification.replaces_id);
-    notibox
-        .imp()
-        .reset
-        .store(true, std::sync::atomic::Ordering::SeqCst);
+    notibox.imp().notification_id.set(notification.id);
+    notibox.imp().timeout.store(
+        timeout_millis(notification.expire_timeout, &config),
+        std::sync::atomic::Ordering::SeqCst,
+    );
     notibox.set_size_request(120, 5);
     let urgency_string = notification.urgency.to_str();
     notibox.style_context().add_class(urgency_string);
@@ -128,11 +195,45 @@ pub fn show_notification(
         .imp()
         .previous_urgency
         .set(urgency_string.to_string());
+    if let Some(category) = &notification.category {
+        let category_string = category_class(category);
+        notibox.style_context().add_class(&category_string);
+        notibox.imp().previous_category.set(category_string);
+    }
+    for css_class in notification.css_classes.iter() {
+        notibox.style_context().add_class(css_class);
+    }
+    notibox
+        .imp()
+        .previous_classes
+        .set(notification.css_classes.clone());
 
     let noticlone = notibox.clone();
     let noticlone2 = notibox.clone();
     let notiimp = noticlone2.imp();
 
+    // actions, the default action is invoked by clicking the notification itself
+    // resident notifications stay after an action has been invoked
+    notiimp
+        .has_default_action
+        .set(notification.has_action("default"));
+    notiimp.resident.set(notification.resident);
+    let desktop_entry = notification.desktop_entry.clone();
+    let actionbox = Box::new(gtk::Orientation::Horizontal, 5);
+    actionbox.style_context().add_class("actionbox");
+    actionbox.set_homogeneous(true);
+    notiimp.has_actions.set(set_actions(
+        &actionbox,
+        &notification,
+        mainbox,
+        window,
+        noticount.clone(),
+        id_map.clone(),
+        mutex.clone(),
+    ));
+    let mut notiactionbox = notiimp.actionbox.borrow_mut();
+    *notiactionbox = actionbox;
+
     let basebox = Box::new(gtk::Orientation::Vertical, 5);
     let regularbox = Box::new(gtk::Orientation::Horizontal, 5);
 
@@ -140,7 +241,8 @@ pub fn show_notification(
     bodybox.style_context().add_class("bodybox");
 
     // app name
-    let app_name = Label::new(Some(&notification.app_name));
+    let app_name = Label::new(Some(notification.display_name()));
+    let fallback_icon = notification.fallback_icon();
     app_name.style_context().add_class("appname");
     app_name.set_valign(Align::Center);
     app_name.set_halign(Align::Center);
@@ -151,7 +253,18 @@ pub fn show_notification(
     // summary
     if notification.summary != "" {
         notiimp.has_summary.set(true);
-        let summary = Label::new(Some(&notification.summary));
+        let summary = Label::new(None);
+        summary.set_markup(&parse_markup(&notification.summary).text);
+        let id = notification.id;
+        let id_map = id_map.clone();
+        let mutex = mutex.clone();
+        connect_links(
+            &summary,
+            config.clone(),
+            clone!(@weak noticount, @weak mainbox, @weak window => move || {
+                remove_notification(&mainbox, &window, noticount, id, id_map.clone(), CloseReason::Dismissed, mutex.clone());
+            }),
+        );
         summary.style_context().add_class("summary");
         summary.set_wrap_mode(pango::WrapMode::Word);
         summary.set_width_chars(15);
@@ -167,16 +280,24 @@ pub fn show_notification(
     // body
     if notification.body != "" {
         notiimp.has_body.set(true);
-        let (body, text_css, has_image) = class_from_html(notification.body);
+        let markup = parse_markup(&notification.body);
         let text = Label::new(None);
-        if has_image {
-            has_body_image = has_image;
-            image_path = text_css;
-        } else {
-            text.style_context().add_class(&text_css);
+        if let Some(path) = markup.images.into_iter().next() {
+            has_body_image = true;
+            image_path = path;
         }
         text.style_context().add_class("text");
-        text.set_markup(body.as_str());
+        text.set_markup(&markup.text);
+        let id = notification.id;
+        let id_map = id_map.clone();
+        let mutex = mutex.clone();
+        connect_links(
+            &text,
+            config.clone(),
+            clone!(@weak noticount, @weak mainbox, @weak window => move || {
+                remove_notification(&mainbox, &window, noticount, id, id_map.clone(), CloseReason::Dismissed, mutex.clone());
+            }),
+        );
         text.set_wrap_mode(pango::WrapMode::Word);
         text.set_width_chars(15);
         text.set_line_wrap(true);
@@ -192,6 +313,10 @@ pub fn show_notification(
     bodybox.set_halign(gtk::Align::Fill);
     notibox.add(&notibutton);
 
+    if notiimp.has_actions.get() {
+        notibox.add(&*notiactionbox);
+    }
+
     // image
     let image = Image::new();
     if has_body_image
@@ -199,12 +324,14 @@ pub fn show_notification(
             notification.image_data.clone(),
             Some(image_path),
             notification.app_icon.clone(),
+            fallback_icon.clone(),
             &image,
         )
         || set_image(
             notification.image_data,
             notification.image_path,
             notification.app_icon,
+            fallback_icon,
             &image,
         )
     {
@@ -245,7 +372,7 @@ pub fn show_notification(
         let mut shared_inline_reply = notiimp.inline_reply.borrow_mut();
         inline_reply.connect_activate(
             clone!(@weak window,@weak notiimp, @weak noticount, @weak mainbox => move |entry| {
-                let id = notification.replaces_id;
+                let id = notification.id;
                 let text = entry.text().to_string();
                 notiimp.reply_open.store(false, std::sync::atomic::Ordering::SeqCst);
                 activate_inline_reply(mainbox, id, noticount, window, id_map_clone.clone(), text, mutexclone.clone());
@@ -277,28 +404,23 @@ pub fn show_notification(
     let id_map_clone = id_map.clone();
     let id = notibox.imp().notification_id.get();
     notibutton.connect_clicked(
-        clone!(@weak noticount, @weak mainbox, @weak window => move |_| {
-        let id_clone = id;
-        thread::spawn(move || {
-            use dbus::blocking::Connection;
-
-            let conn = Connection::new_session().unwrap();
-            let proxy = conn.with_proxy(
-                "org.freedesktop.Notifications",
-                "/org/freedesktop/Notifications",
-                Duration::from_millis(1000),
-            );
-            let _: Result<(), dbus::Error> =
-                proxy.method_call("org.freedesktop.Notifications", "InvokeAction", (id_clone,"default"));
-        });
-            remove_notification(&mainbox, &window, noticount, id, id_map.clone(), false, mutexclone2.clone());
+        clone!(@weak noticount, @weak mainbox, @weak window, @weak notibox => move |button| {
+            let notiimp = notibox.imp();
+            if notiimp.has_default_action.get() {
+                let token = activation_token(button, desktop_entry.as_deref());
+                invoke_action(id, "default".to_string(), token);
+                if notiimp.resident.get() {
+                    return;
+                }
+            }
+            remove_notification(&mainbox, &window, noticount, id, id_map.clone(), CloseReason::Dismissed, mutexclone2.clone());
         }),
     );
 
     id_map_clone
         .write()
         .unwrap()
-        .insert(notification.replaces_id, noticlone);
+        .insert(notification.id, noticlone);
     mainbox.add(&*notibox);
 
     let mut notibodybox = notiimp.bodybox.borrow_mut();
@@ -312,11 +434,28 @@ pub fn show_notification(
     notibutton.set_child(Some(&*notibasebox));
 
     // thread removes notification after timeout
+    // a timeout of 0 never expires, but the notification might get a timeout with a modification
     thread::spawn(clone!(@weak notibox => move || {
-        thread::sleep(Duration::from_secs(config.timeout));
-        while notibox.imp().reset.load(std::sync::atomic::Ordering::SeqCst) == true || notibox.imp().reply_open.load(std::sync::atomic::Ordering::SeqCst) == true {
-            notibox.imp().reset.store(false, std::sync::atomic::Ordering::SeqCst);
-            thread::sleep(Duration::from_secs(config.timeout));
+        let notiimp = notibox.imp();
+        notiimp.reset.store(false, std::sync::atomic::Ordering::SeqCst);
+        loop {
+            if *notiimp.removed.lock().unwrap() {
+                return;
+            }
+            let timeout = notiimp.timeout.load(std::sync::atomic::Ordering::SeqCst);
+            if timeout == 0 {
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+            thread::sleep(Duration::from_millis(timeout));
+            if !notiimp.reset.swap(false, std::sync::atomic::Ordering::SeqCst)
+                && !notiimp.reply_open.load(std::sync::atomic::Ordering::SeqCst)
+            {
+                break;
+            }
+        }
+        if *notiimp.removed.lock().unwrap() {
+            return;
         }
         tx2.send(notibox).unwrap();
     }));
@@ -330,9 +469,11 @@ pub fn modify_notification(
     notification: Notification,
     id_map: Arc<RwLock<HashMap<u32, Arc<NotificationBox>>>>,
     mutex: Arc<Mutex<bool>>,
+    config: Arc<Config>,
 ) {
     let _guard = mutex.lock().unwrap();
-    let id = notification.replaces_id;
+    let id = notification.id;
+    let fallback_icon = notification.fallback_icon();
     let map = id_map.write().unwrap();
     let mut notibox = map.get(&id);
     let notibox_borrow_opt = notibox.borrow_mut();
@@ -347,6 +488,10 @@ pub fn modify_notification(
     notiimp
         .reset
         .store(true, std::sync::atomic::Ordering::SeqCst);
+    notiimp.timeout.store(
+        timeout_millis(notification.expire_timeout, &config),
+        std::sync::atomic::Ordering::SeqCst,
+    );
     notibox_borrow.style_context().restore();
     let urgency_string = notification.urgency.to_str();
     notibox_borrow
@@ -354,6 +499,26 @@ pub fn modify_notification(
         .remove_class(&notiimp.previous_urgency.take());
     notiimp.previous_urgency.set(urgency_string.to_string());
     notibox_borrow.style_context().add_class(urgency_string);
+    let previous_category = notiimp.previous_category.take();
+    if !previous_category.is_empty() {
+        notibox_borrow
+            .style_context()
+            .remove_class(&previous_category);
+    }
+    if let Some(category) = &notification.category {
+        let category_string = category_class(category);
+        notibox_borrow.style_context().add_class(&category_string);
+        notiimp.previous_category.set(category_string);
+    }
+    for css_class in notiimp.previous_classes.take() {
+        notibox_borrow.style_context().remove_class(&css_class);
+    }
+    for css_class in notification.css_classes.iter() {
+        notibox_borrow.style_context().add_class(css_class);
+    }
+    notiimp
+        .previous_classes
+        .set(notification.css_classes.clone());
 
     // progress bar
     let exists = notiimp.has_progbar.get();
@@ -392,7 +557,7 @@ pub fn modify_notification(
             let id_map_clone = id_map.clone();
             newentry.connect_activate(
             clone!(@weak window, @weak notiimp, @weak noticount, @weak mainbox => move |entry| {
-                let id = notification.replaces_id;
+                let id = notification.id;
                 let text = entry.text().to_string();
                 notiimp.reply_open.store(false, std::sync::atomic::Ordering::SeqCst);
                 activate_inline_reply(mainbox, id, noticount, window, id_map_clone.clone(), text, mutexclone.clone());
@@ -418,22 +583,54 @@ pub fn modify_notification(
         }
     }
 
+    // actions
+    let exists = notiimp.has_actions.get();
+    let actionbox = notiimp.actionbox.borrow();
+    let has_actions = set_actions(
+        &actionbox,
+        &notification,
+        mainbox,
+        window,
+        noticount.clone(),
+        id_map.clone(),
+        mutex.clone(),
+    );
+    if has_actions && !exists {
+        notibox_borrow.add(&*actionbox);
+    } else if !has_actions && exists {
+        notibox_borrow.remove(&*actionbox);
+    }
+    actionbox.show_all();
+    notiimp.has_actions.set(has_actions);
+    notiimp
+        .has_default_action
+        .set(notification.has_action("default"));
+    notiimp.resident.set(notification.resident);
+
     // summary
     let exists = notiimp.has_summary.get();
     if notification.summary == "" && exists {
         notibodybox.remove(&notiimp.summary.take());
         notiimp.has_summary.set(false);
     } else if notification.summary != "" {
-        let (text, css_classes, _) = class_from_html(notification.summary);
+        let markup = parse_markup(&notification.summary);
         let mut text_borrow = notiimp.summary.borrow_mut();
         if !exists {
             *text_borrow = Label::new(None);
+            let id_map = id_map.clone();
+            let mutex = mutex.clone();
+            connect_links(
+                &text_borrow,
+                config.clone(),
+                clone!(@weak noticount, @weak mainbox, @weak window => move || {
+                    remove_notification(&mainbox, &window, noticount, id, id_map.clone(), CloseReason::Dismissed, mutex.clone());
+                }),
+            );
             notibodybox.add(&*text_borrow);
             notiimp.has_summary.set(true);
         }
-        text_borrow.set_text(text.as_str());
+        text_borrow.set_markup(&markup.text);
         text_borrow.style_context().add_class("summary");
-        text_borrow.style_context().add_class(&css_classes);
     }
 
     let mut has_body_image = false;
@@ -445,20 +642,27 @@ pub fn modify_notification(
         notibodybox.remove(&notiimp.body.take());
         notiimp.has_body.set(false);
     } else if notification.body != "" {
-        let (text, css_classes, has_image) = class_from_html(notification.body);
+        let markup = parse_markup(&notification.body);
         let mut text_borrow = notiimp.body.borrow_mut();
         if !exists {
             *text_borrow = Label::new(None);
+            let id_map = id_map.clone();
+            let mutex = mutex.clone();
+            connect_links(
+                &text_borrow,
+                config.clone(),
+                clone!(@weak noticount, @weak mainbox, @weak window => move || {
+                    remove_notification(&mainbox, &window, noticount, id, id_map.clone(), CloseReason::Dismissed, mutex.clone());
+                }),
+            );
             notibodybox.add(&*text_borrow);
             notiimp.has_body.set(true);
         }
-        if has_image {
-            has_body_image = has_image;
-            body_image_path = css_classes;
-        } else {
-            text_borrow.style_context().add_class(&css_classes);
+        if let Some(path) = markup.images.into_iter().next() {
+            has_body_image = true;
+            body_image_path = path;
         }
-        text_borrow.set_text(text.as_str());
+        text_borrow.set_markup(&markup.text);
         text_borrow.style_context().add_class("text");
     }
 
@@ -481,6 +685,7 @@ pub fn modify_notification(
                 notification.image_data.clone(),
                 Some(body_image_path),
                 notification.app_icon.clone(),
+                fallback_icon,
                 &image_borrow,
             );
         } else {
@@ -488,14 +693,52 @@ pub fn modify_notification(
                 notification.image_data,
                 notification.image_path,
                 notification.app_icon,
+                fallback_icon,
                 &image_borrow,
             );
         }
     }
 }
 
-pub fn initialize_ui(css_string: String, config_file: String) {
-    let app = Application::builder().application_id(APP_ID).build();
+/// Forwards notifications from the daemon thread to the popups on the main thread.
+/// Each notification is sent along with the current config, so reloads apply to new popups.
+struct PopupSink {
+    shown: Sender<(Notification, Arc<Config>)>,
+    closed: Sender<(u32, CloseReason)>,
+    config: Mutex<Arc<Config>>,
+    tokens: ActivationTokens,
+}
+
+impl NotificationSink for PopupSink {
+    fn show(&self, notification: Notification) {
+        let config = self.config.lock().unwrap().clone();
+        self.shown
+            .send((notification, config))
+            .expect("Failed to send notification.");
+    }
+    fn close(&self, id: u32, reason: CloseReason) {
+        self.closed
+            .send((id, reason))
+            .expect("Failed to send closed notification.");
+    }
+    fn reload(&self, config: Arc<Config>) {
+        *self.config.lock().unwrap() = config;
+    }
+    fn activation_token(&self, id: u32) -> Option<String> {
+        self.tokens.lock().unwrap().remove(&id)
+    }
+}
+
+pub fn initialize_ui(css_string: String, config_file: String, options: DaemonOptions) {
+    BUS_NAME
+        .set(options.bus_name.clone())
+        .expect("The ui can only be initialized once.");
+    // a unique application would hand a second process over to the running one,
+    // --replace and --bus-name need every process to run its own daemon
+    let app = Application::builder()
+        .application_id(APP_ID)
+        .flags(ApplicationFlags::NON_UNIQUE)
+        .build();
     app.connect_startup(move |_| {
         if !gtk::is_initialized() {
             gtk::init().unwrap();
@@ -503,18 +746,40 @@ pub fn initialize_ui(css_string: String, config_file: String) {
         load_css(&css_string);
     });
 
+    // activate runs again whenever the application is activated, the daemon and window only start once
+    let activated = Cell::new(false);
     app.connect_activate(move |app| {
+        if activated.replace(true) {
+            return;
+        }
         let (tx, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
         let (tx2_initial, rx2) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let (tx3, rx3) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
         let tx2 = Arc::new(tx2_initial);
         let config = Arc::new(parse_config(&config_file));
-        let configrc = config.clone();
+        let options = options.clone();
+        let tokens = ActivationTokens::default();
+        start_daemon_calls(tokens.clone());
         thread::spawn(move || {
-            let mut server = NotificationServer::create(tx);
-            server.run(configrc);
+            let sink = PopupSink {
+                shown: tx,
+                closed: tx3,
+                config: Mutex::new(config.clone()),
+                tokens,
+            };
+            let mut server = NotificationServer::create(sink, config);
+            // the popups are useless without the daemon, so the whole process exits with it
+            match server.run(options) {
+                Ok(()) => process::exit(0),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                }
+            }
         });
         let lock = Arc::new(Mutex::new(false));
         let lock2 = lock.clone();
+        let lock3 = lock.clone();
         let mainbox = Box::new(gtk::Orientation::Vertical, 5);
         mainbox.style_context().add_class("MainBox");
         let window = Window::builder()
@@ -542,13 +807,16 @@ pub fn initialize_ui(css_string: String, config_file: String) {
 
         let windowrc = window.clone();
         let windowrc2 = windowrc.clone();
+        let windowrc3 = windowrc.clone();
 
         // used in order to not close the window if we still have notifications
         let noticount = Arc::new(Cell::new(0));
         let noticount2 = noticount.clone();
+        let noticount3 = noticount.clone();
 
         let id_map = Arc::new(RwLock::new(HashMap::<u32, Arc<NotificationBox>>::new()));
         let id_map_clone = id_map.clone();
+        let id_map_clone2 = id_map.clone();
 
         let action_present = SimpleAction::new("present", None);
 
@@ -557,18 +825,14 @@ pub fn initialize_ui(css_string: String, config_file: String) {
         }));
 
         let mainbox2 = mainbox.clone();
+        let mainbox3 = mainbox.clone();
         mainbox.set_hexpand_set(false);
         mainbox.set_vexpand_set(true);
         mainbox.set_size_request(120, 120);
 
         // new notification added
-        rx.attach(None, move |notification| {
-            if id_map
-                .read()
-                .unwrap()
-                .get(&notification.replaces_id)
-                .is_none()
-            {
+        rx.attach(None, move |(notification, config)| {
+            if id_map.read().unwrap().get(&notification.id).is_none() {
                 show_notification(
                     noticount.clone(),
                     &mainbox,
@@ -577,7 +841,7 @@ pub fn initialize_ui(css_string: String, config_file: String) {
                     tx2.clone(),
                     id_map.clone(),
                     lock2.clone(),
-                    config.clone(),
+                    config,
                 );
             } else {
                 // modify notification if id is already in map
@@ -588,6 +852,7 @@ pub fn initialize_ui(css_string: String, config_file: String) {
                     notification,
                     id_map.clone(),
                     lock2.clone(),
+                    config,
                 );
             }
             glib::Continue(true)
@@ -602,11 +867,24 @@ pub fn initialize_ui(css_string: String, config_file: String) {
                 noticount2.clone(),
                 id,
                 id_map_clone.clone(),
-                true,
+                CloseReason::Expired,
                 lock.clone(),
             );
             glib::Continue(true)
         });
+        // notification closed by the daemon, e.g. via CloseNotification
+        rx3.attach(None, move |(id, reason)| {
+            remove_notification(
+                &mainbox3,
+                &windowrc3,
+                noticount3.clone(),
+                id,
+                id_map_clone2.clone(),
+                reason,
+                lock3.clone(),
+            );
+            glib::Continue(true)
+        });
     });
 
     fn load_css(css_string: &String) {
@@ -626,31 +904,152 @@ pub fn initialize_ui(css_string: String, config_file: String) {
     app.run_with_args(&[""]);
 }
 
-fn class_from_html(mut body: String) -> (String, String, bool) {
-    let mut ret: &str = "";
-    let mut retstring = body.clone();
-    let has_image: bool;
-    if body.contains("<br><img src=\"file:///") {
-        let split = retstring.split_once("<br><img src=\"file:///").unwrap();
-        body = split.0.to_string() + "sent an image.".into();
-        retstring = split.1.to_string();
-        let split = retstring.split_once("\"");
-        if split.is_some() {
-            ret = split.unwrap().0;
-            has_image = true;
+/// Fills the actionbox with a button for each action of the notification.
+/// With the action-icons hint, keys are used as icon names and the label becomes the tooltip.
+/// Returns false if there are no actions that need a button.
+fn set_actions(
+    actionbox: &Box,
+    notification: &Notification,
+    mainbox: &Box,
+    window: &Window,
+    noticount: Arc<Cell<i32>>,
+    id_map: Arc<RwLock<HashMap<u32, Arc<NotificationBox>>>>,
+    mutex: Arc<Mutex<bool>>,
+) -> bool {
+    for child in actionbox.children() {
+        actionbox.remove(&child);
+    }
+    let id = notification.id;
+    let resident = notification.resident;
+    let desktop_entry = notification.desktop_entry.clone();
+    let actions = notification.action_pairs();
+    let icon_theme = IconTheme::default();
+    for (key, label) in actions.iter() {
+        let button = Button::new();
+        button.style_context().add_class("action");
+        if notification.action_icons && icon_theme.as_ref().is_some_and(|theme| theme.has_icon(key))
+        {
+            let image = Image::from_icon_name(Some(key), IconSize::Button);
+            button.set_image(Some(&image));
+            button.set_always_show_image(true);
+            button.set_tooltip_text(Some(label));
+            button.style_context().add_class("action-icon");
         } else {
-            has_image = false;
+            button.set_label(label);
         }
-    } else {
-        has_image = false;
+        let key = key.clone();
+        let desktop_entry = desktop_entry.clone();
+        let id_map = id_map.clone();
+        let mutex = mutex.clone();
+        button.connect_clicked(
+            clone!(@weak noticount, @weak mainbox, @weak window => move |button| {
+                let token = activation_token(button, desktop_entry.as_deref());
+                invoke_action(id, key.clone(), token);
+                if resident {
+                    return;
+                }
+                remove_notification(&mainbox, &window, noticount, id, id_map.clone(), CloseReason::Dismissed, mutex.clone());
+            }),
+        );
+        actionbox.add(&button);
+    }
+    !actions.is_empty()
+}
+
+/// Invokes an action, the activation token of the click is sent along with it.
+fn invoke_action(id: u32, action: String, token: Option<String>) {
+    call_daemon(DaemonCall::InvokeAction(id, action, token));
+}
+
+/// Requests an activation token for the click on a widget of the popup, xdg-activation on wayland and a startup id on X11.
+/// Has to be called while handling the click, the compositor only hands out tokens for the latest input on the popup.
+/// The app info only names the application that gets activated, a command line is used when there is no desktop entry.
+fn activation_token(
+    clicked: &impl glib::IsA<gtk::Widget>,
+    desktop_entry: Option<&str>,
+) -> Option<String> {
+    let context = clicked.display().app_launch_context()?;
+    context.set_timestamp(gtk::current_event_time());
+    let info = match desktop_entry
+        .and_then(|entry| gio::DesktopAppInfo::new(&format!("{}.desktop", entry)))
+    {
+        Some(info) => info.upcast::<gio::AppInfo>(),
+        None => {
+            gio::AppInfo::create_from_commandline("true", desktop_entry, AppInfoCreateFlags::NONE)
+                .ok()?
+        }
+    };
+    context
+        .startup_notify_id(&info, &[])
+        .map(|token| token.to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// Opens activated links with the configured opener and dismisses the notification afterwards.
+/// Links with a scheme that is not allowed by the config are refused.
+fn connect_links(label: &Label, config: Arc<Config>, dismiss: impl Fn() + 'static) {
+    label.set_track_visited_links(false);
+    label.connect_activate_link(move |_, uri| {
+        match config.allowed_link_scheme(uri) {
+            Ok(_) => {
+                open_link(&config.link_opener, uri);
+                dismiss();
+            }
+            Err(error) => println!("Refusing to open {}: {}", uri, error),
+        }
+        Inhibit(true)
+    });
+}
+
+/// Opens a link with the given command, "portal" uses the OpenURI portal instead.
+fn open_link(link_opener: &str, uri: &str) {
+    if link_opener != "portal" {
+        if let Err(error) = spawn_command(link_opener, [uri]) {
+            println!("Could not open {} with {}: {}", uri, link_opener, error);
+        }
+        return;
     }
-    (body, String::from(ret), has_image)
+    let uri = uri.to_string();
+    thread::spawn(move || {
+        use dbus::{arg::PropMap, blocking::Connection};
+
+        let conn = Connection::new_session().unwrap();
+        let proxy = conn.with_proxy(
+            "org.freedesktop.portal.Desktop",
+            "/org/freedesktop/portal/desktop",
+            Duration::from_millis(1000),
+        );
+        let result: Result<(dbus::Path,), dbus::Error> = proxy.method_call(
+            "org.freedesktop.portal.OpenURI",
+            "OpenURI",
+            ("", uri.as_str(), PropMap::new()),
+        );
+        if let Err(error) = result {
+            println!("Could not open {} with the OpenURI portal: {}", uri, error);
+        }
+    });
+}
+
+/// Converts a category like "email.arrived" to the css class "category-email-arrived".
+fn category_class(category: &str) -> String {
+    let sanitized: String = category
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("category-{}", sanitized)
 }
 
 fn set_image(
     data: Option<ImageData>,
     picture: Option<String>,
     icon: String,
+    fallback_icon: Option<String>,
     image: &Image,
 ) -> bool {
     let mut pixbuf: Option<Pixbuf> = None;
@@ -659,18 +1058,19 @@ fn set_image(
             .unwrap()
             .scale_simple(100, 100, gtk::gdk_pixbuf::InterpType::Bilinear)
     };
-    let use_icon = |mut _pixbuf: Option<Pixbuf>| {
-        if Path::new(&icon).is_file() {
-            let maybe_pix = Pixbuf::from_file_at_size(&icon, 100, 100);
+    let set_icon = |icon: &str| {
+        if Path::new(icon).is_file() {
+            let maybe_pix = Pixbuf::from_file_at_size(icon, 100, 100);
             if maybe_pix.is_ok() {
                 image.set_pixbuf(Some(&maybe_pix.unwrap()));
                 image.style_context().add_class("picture");
             }
         } else {
-            image.set_icon_name(Some(icon.as_str()));
+            image.set_icon_name(Some(icon));
             image.style_context().add_class("image");
         }
     };
+    let use_icon = |mut _pixbuf: Option<Pixbuf>| set_icon(&icon);
 
     if let Some(path_opt) = picture {
         if Path::new(&path_opt).is_file() {
@@ -690,12 +1090,8 @@ fn set_image(
         (use_icon)(pixbuf);
         return true;
     }
-    if data.is_some() {
-        let image_data = data.unwrap();
+    if let Some(image_data) = data.and_then(|data| data.to_rgb8()) {
         let bytes = gtk::glib::Bytes::from(&image_data.data);
-        if bytes.is_empty() {
-            return false;
-        }
         pixbuf = Some(Pixbuf::from_bytes(
             &bytes,
             gtk::gdk_pixbuf::Colorspace::Rgb,
@@ -710,6 +1106,10 @@ fn set_image(
         image.style_context().add_class("picture");
         return true;
     }
+    if let Some(fallback_icon) = fallback_icon {
+        set_icon(&fallback_icon);
+        return true;
+    }
     false
 }
 
@@ -722,18 +1122,15 @@ pub fn activate_inline_reply(
     text: String,
     mutex: Arc<Mutex<bool>>,
 ) {
-    thread::spawn(move || {
-        use dbus::blocking::Connection;
-
-        let conn = Connection::new_session().unwrap();
-        let proxy = conn.with_proxy(
-            "org.freedesktop.Notifications",
-            "/org/freedesktop/Notifications",
-            Duration::from_millis(1000),
-        );
-        let _: Result<(), dbus::Error> =
-            proxy.method_call("org.freedesktop.Notifications", "InlineReply", (id, text));
-    });
+    call_daemon(DaemonCall::InlineReply(id, text));
     gtk_layer_shell::set_keyboard_interactivity(&window, false);
-    remove_notification(&mainbox, &window, noticount, id, id_map, false, mutex);
+    remove_notification(
+        &mainbox,
+        &window,
+        noticount,
+        id,
+        id_map,
+        CloseReason::Dismissed,
+        mutex,
+    );
 }
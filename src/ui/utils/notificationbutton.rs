@@ -17,7 +17,7 @@ along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 use std::cell::{Cell, RefCell};
 use std::sync::Mutex;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 
 use gtk::subclass::prelude::*;
 use gtk::{glib, Image, Label, ProgressBar};
@@ -28,6 +28,7 @@ pub struct NotificationBox {
     pub removed: Mutex<bool>,
     pub fraction: RefCell<ProgressBar>,
     pub inline_reply: RefCell<gtk::Entry>,
+    pub actionbox: RefCell<gtk::Box>,
     pub body: RefCell<Label>,
     pub summary: RefCell<Label>,
     pub image: RefCell<Image>,
@@ -35,13 +36,19 @@ pub struct NotificationBox {
     pub regularbox: RefCell<gtk::Box>,
     pub bodybox: RefCell<gtk::Box>,
     pub reset: AtomicBool,
+    pub timeout: AtomicU64,
     pub has_body: Cell<bool>,
     pub has_summary: Cell<bool>,
     pub has_image: Cell<bool>,
     pub has_progbar: Cell<bool>,
     pub has_inline_reply: Cell<bool>,
+    pub has_actions: Cell<bool>,
+    pub has_default_action: Cell<bool>,
+    pub resident: Cell<bool>,
     pub reply_open: AtomicBool,
     pub previous_urgency: Cell<String>,
+    pub previous_category: Cell<String>,
+    pub previous_classes: Cell<Vec<String>>,
 }
 
 #[glib::object_subclass]